@@ -0,0 +1,55 @@
+// adaptive sampling: every pixel gets a few samples, then the ones that are still noisy keep
+// getting more until they settle down or hit the maximum. flat walls are done quickly and the
+// samples they didn't need go to shadows, caustics and whatever else is hard
+use serde::Deserialize;
+
+use crate::Color;
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Adaptive {
+    // every pixel gets at least this many, the noise estimate needs a few to go on
+    pub(crate) min_samples_per_pixel: u32,
+    pub(crate) max_samples_per_pixel: u32,
+    // how small the error of a pixel has to get, see error
+    pub(crate) threshold: f64,
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Adaptive {
+            min_samples_per_pixel: 16,
+            max_samples_per_pixel: 1024,
+            threshold: 0.02,
+        }
+    }
+}
+
+impl Adaptive {
+    pub(crate) fn check(&self) -> Result<(), String> {
+        if self.min_samples_per_pixel < 2 {
+            return Err("the minimum has to be at least 2 samples per pixel".to_string());
+        }
+        if self.max_samples_per_pixel < self.min_samples_per_pixel {
+            return Err("the maximum samples per pixel can't be below the minimum".to_string());
+        }
+        if self.threshold.is_nan() || self.threshold <= 0.0 {
+            return Err("the threshold has to be above 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+// the standard error of the pixel's average luminance over the square root of the average. that's
+// roughly how much the noise shows once the gamma curve has brightened the darks, dividing by the
+// average itself would call a dark pixel done long before it looks it
+pub(crate) fn error(color: &Color, luminance_squared: f64, samples: u32) -> f64 {
+    if samples < 2 {
+        return f64::INFINITY;
+    }
+    let n = samples as f64;
+    let mean = color.luminance() / n;
+    // of single samples, n - 1 since the mean comes from the same samples
+    let variance = ((luminance_squared / n - mean * mean) * n / (n - 1.0)).max(0.0);
+    (variance / n).sqrt() / (mean.max(0.0) + 1e-4).sqrt()
+}
@@ -0,0 +1,196 @@
+// arbitrary output variables, pictures of what the camera rays hit first instead of the light
+// coming from there. for compositing, and for seeing what the renderer sees when something looks off
+use std::sync::Arc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::bvh::Aabb;
+use crate::features::Features;
+use crate::rng::hash;
+use crate::sampler::Sampler;
+use crate::tonemap::{to_byte, Display};
+use crate::{Color, HitRecord, Hitable, Material, Ray, Scatter, Vec3};
+
+// what buf shows
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Aov {
+    // the rendered picture
+    Beauty,
+    // distance from the camera, the closer the lighter
+    Depth,
+    // in world space, x y z from -1 to 1 as red green blue from dark to bright
+    Normal,
+    Albedo,
+    // a color for each one, black where there is none
+    MaterialId,
+    ObjectId,
+    // how many samples each pixel got, brighter for more. only interesting with adaptive sampling
+    SampleCount,
+}
+
+// flat copies of the features for js, only filled in when asked for
+#[derive(Default)]
+pub(crate) struct AovBuffers {
+    // one per pixel
+    pub(crate) depth: Vec<f32>,
+    // three per pixel
+    pub(crate) normal: Vec<f32>,
+    pub(crate) albedo: Vec<f32>,
+    // one per pixel
+    pub(crate) material_id: Vec<u32>,
+    pub(crate) object_id: Vec<u32>,
+}
+
+// features is a sum over samples, depth_scale the depth that comes out middle gray and
+// max_samples the most any pixel got
+pub(crate) fn pixel(aov: Aov, features: &Features, samples: u32, depth_scale: f64, max_samples: u32) -> [u8; 4] {
+    match aov {
+        // that's the renderer's job
+        Aov::Beauty => [0, 0, 0, 255],
+        Aov::Depth => {
+            if features.hits == 0.0 {
+                return [0, 0, 0, 255];
+            }
+            // fades towards black in the distance without ever quite getting there
+            let value = to_byte(depth_scale / (depth_scale + depth(features)));
+            [value, value, value, 255]
+        }
+        Aov::Normal => {
+            let [x, y, z] = normal(features);
+            if [x, y, z] == [0.0; 3] {
+                return [0, 0, 0, 255];
+            }
+            let channel = |value: f32| to_byte(0.5 * value as f64 + 0.5);
+            [channel(x), channel(y), channel(z), 255]
+        }
+        // like a color, but without exposure or tone mapping since it can't be brighter than white
+        Aov::Albedo => Display::default().bytes(&(features.albedo.clone() * (1.0 / samples.max(1) as f64))),
+        Aov::MaterialId => id_color(features.material_id),
+        Aov::ObjectId => id_color(features.object_id),
+        Aov::SampleCount => {
+            if samples == 0 {
+                return [0, 0, 0, 255];
+            }
+            // on a log scale, the minimum is usually a lot less than the maximum
+            let value = if max_samples > 1 {
+                libm::log(samples as f64) / libm::log(max_samples as f64)
+            } else {
+                1.0
+            };
+            heat_color(value)
+        }
+    }
+}
+
+// black through purple, red and yellow to white for 0 to 1
+fn heat_color(value: f64) -> [u8; 4] {
+    const STOPS: [[f64; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [0.35, 0.05, 0.5],
+        [0.85, 0.15, 0.2],
+        [1.0, 0.7, 0.0],
+        [1.0, 1.0, 1.0],
+    ];
+    let position = value.clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let i = (position as usize).min(STOPS.len() - 2);
+    let t = position - i as f64;
+    let channel = |c: usize| to_byte(STOPS[i][c] + t * (STOPS[i + 1][c] - STOPS[i][c]));
+    [channel(0), channel(1), channel(2), 255]
+}
+
+// the median depth of the pixels that hit something. a floor that goes on forever would make the
+// largest one useless
+pub(crate) fn depth_scale(features: &[Features]) -> f64 {
+    let mut depths: Vec<f64> = features.iter()
+        .filter(|features| features.hits > 0.0)
+        .map(depth)
+        .collect();
+    if depths.is_empty() {
+        return 1.0;
+    }
+    let middle = depths.len() / 2;
+    let (_, median, _) = depths.select_nth_unstable_by(middle, |a, b| a.total_cmp(b));
+    median.max(1e-6)
+}
+
+// the average over the samples that hit something
+pub(crate) fn depth(features: &Features) -> f64 {
+    if features.hits > 0.0 { features.depth / features.hits } else { 0.0 }
+}
+
+// unit length, or 0 where nothing got hit
+pub(crate) fn normal(features: &Features) -> [f32; 3] {
+    let length = features.normal.length();
+    if length > 0.0 {
+        let normal = features.normal.clone() / length;
+        [normal.x as f32, normal.y as f32, normal.z as f32]
+    } else {
+        [0.0; 3]
+    }
+}
+
+// the same id gets the same color every time, neighbouring ids look nothing alike
+fn id_color(id: u32) -> [u8; 4] {
+    if id == 0 {
+        return [0, 0, 0, 255];
+    }
+    let bits = hash(&[id as u64]);
+    // not too dark so they don't get mixed up with black
+    let channel = |shift: u32| 64 + ((bits >> shift) & 0xff) as u8 / 4 * 3;
+    [channel(0), channel(8), channel(16), 255]
+}
+
+// a material from the scene's list, with the number the material id aov shows for it
+pub(crate) struct NumberedMaterial {
+    pub(crate) id: u32,
+    pub(crate) material: Arc<dyn Material>,
+}
+
+impl Material for NumberedMaterial {
+    fn scatter(&self, ray: &Ray, hitrecord: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        self.material.scatter(ray, hitrecord, sampler)
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hitrecord: &HitRecord, scattered: &Ray) -> f64 {
+        self.material.scattering_pdf(ray, hitrecord, scattered)
+    }
+
+    fn emitted(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        self.material.emitted(u, v, point)
+    }
+
+    fn albedo(&self, hitrecord: &HitRecord) -> Color {
+        self.material.albedo(hitrecord)
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+// one of the scene's objects, with the number the object id aov shows for it
+pub(crate) struct NumberedObject {
+    pub(crate) id: u32,
+    pub(crate) hitable: Box<dyn Hitable>,
+}
+
+impl Hitable for NumberedObject {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut hitrecord = self.hitable.hit(ray, t_min, t_max)?;
+        hitrecord.object_id = self.id;
+        Some(hitrecord)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.hitable.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        self.hitable.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.hitable.random(origin, sampler)
+    }
+}
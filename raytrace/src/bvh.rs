@@ -0,0 +1,240 @@
+// bounding volume hierarchy, so a ray only has to test the objects whose boxes it goes through
+use crate::{HitRecord, Hitable, Ray, Vec3};
+
+#[derive(Clone)]
+pub(crate) struct Aabb {
+    pub(crate) min: Vec3,
+    pub(crate) max: Vec3,
+}
+
+impl Aabb {
+    // slab test, the ray is inside the box where the t intervals of all three axes overlap
+    pub(crate) fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.direction.axis(axis);
+            let mut t0 = (self.min.axis(axis) - ray.start.axis(axis)) * inv_d;
+            let mut t1 = (self.max.axis(axis) - ray.start.axis(axis)) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub(crate) fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vec3 {
+                x: self.min.x.min(other.min.x),
+                y: self.min.y.min(other.min.y),
+                z: self.min.z.min(other.min.z),
+            },
+            max: Vec3 {
+                x: self.max.x.max(other.max.x),
+                y: self.max.y.max(other.max.y),
+                z: self.max.z.max(other.max.z),
+            },
+        }
+    }
+
+    fn surface_area(&self) -> f64 {
+        let d = &self.max - &self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    fn centroid(&self, axis: usize) -> f64 {
+        0.5 * (self.min.axis(axis) + self.max.axis(axis))
+    }
+}
+
+pub(crate) struct BvhNode {
+    bbox: Aabb,
+    left: Box<dyn Hitable>,
+    // leaves with a single object don't have a right side
+    right: Option<Box<dyn Hitable>>,
+}
+
+impl BvhNode {
+    // None if there's nothing to put in the tree. everything has to have a bounding box,
+    // unbounded stuff like infinite planes has to stay in a HitableList next to the tree
+    pub(crate) fn new(hitables: Vec<Box<dyn Hitable>>) -> Option<BvhNode> {
+        let mut boxed: Vec<(Aabb, Box<dyn Hitable>)> = hitables.into_iter()
+            .map(|hitable| {
+                let bbox = hitable.bounding_box().expect("objects in a bvh need a bounding box");
+                (bbox, hitable)
+            })
+            .collect();
+        if boxed.is_empty() {
+            None
+        } else {
+            Some(BvhNode::build(&mut boxed))
+        }
+    }
+
+    fn build(objects: &mut Vec<(Aabb, Box<dyn Hitable>)>) -> BvhNode {
+        if objects.len() <= 2 {
+            let (left_box, left) = objects.remove(0);
+            return match objects.pop() {
+                Some((right_box, right)) => BvhNode {
+                    bbox: left_box.union(&right_box),
+                    left,
+                    right: Some(right),
+                },
+                None => BvhNode { bbox: left_box, left, right: None },
+            };
+        }
+
+        let (axis, split) = best_split(objects);
+        sort_by_centroid(objects, axis);
+        let mut right_objects = objects.split_off(split);
+
+        let left = BvhNode::build(objects);
+        let right = BvhNode::build(&mut right_objects);
+        BvhNode {
+            bbox: left.bbox.union(&right.bbox),
+            left: Box::new(left),
+            right: Some(Box::new(right)),
+        }
+    }
+}
+
+// total_cmp so a NaN centroid from broken input ends up at one end instead of panicking
+fn sort_by_centroid(objects: &mut [(Aabb, Box<dyn Hitable>)], axis: usize) {
+    objects.sort_by(|(a, _), (b, _)| a.centroid(axis).total_cmp(&b.centroid(axis)));
+}
+
+// surface area heuristic: the chance of a ray hitting a box goes with its surface area,
+// so pick the axis and split position with the lowest area * number of objects on both sides
+fn best_split(objects: &mut [(Aabb, Box<dyn Hitable>)]) -> (usize, usize) {
+    let n = objects.len();
+    let mut best = (0, n / 2);
+    let mut best_cost = f64::INFINITY;
+
+    for axis in 0..3 {
+        sort_by_centroid(objects, axis);
+
+        // right_areas[i] is the area of the box around objects[i..]
+        let mut right_areas = vec![0.0; n];
+        let mut bbox = objects[n - 1].0.clone();
+        for i in (1..n).rev() {
+            bbox = bbox.union(&objects[i].0);
+            right_areas[i] = bbox.surface_area();
+        }
+
+        let mut bbox = objects[0].0.clone();
+        for i in 1..n {
+            let cost = i as f64 * bbox.surface_area() + (n - i) as f64 * right_areas[i];
+            if cost < best_cost {
+                best_cost = cost;
+                best = (axis, i);
+            }
+            bbox = bbox.union(&objects[i].0);
+        }
+    }
+
+    best
+}
+
+impl Hitable for BvhNode {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        if !self.bbox.hit(ray, t_min, t_max) {
+            return None;
+        }
+
+        let left = self.left.hit(ray, t_min, t_max);
+        let closest = left.as_ref().map_or(t_max, |hitrecord| hitrecord.time);
+        let right = self.right.as_ref().and_then(|right| right.hit(ray, t_min, closest));
+
+        right.or(left)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::mesh::Triangle;
+    use crate::rng::Pcg32;
+    use crate::texture::SolidColor;
+    use crate::{Color, HitableList, Lambertian, Ray, Sphere, Vec3};
+
+    fn random_vec3(rng: &mut Pcg32, scale: f64) -> Vec3 {
+        Vec3 {
+            x: scale * (2.0 * rng.next_f64() - 1.0),
+            y: scale * (2.0 * rng.next_f64() - 1.0),
+            z: scale * (2.0 * rng.next_f64() - 1.0),
+        }
+    }
+
+    // the same spheres and triangles every time
+    fn objects() -> Vec<Box<dyn Hitable>> {
+        let material = Arc::new(Lambertian {
+            albedo: Arc::new(SolidColor { color: Color { r: 0.5, g: 0.5, b: 0.5, a: 1.0 } }),
+        });
+        let mut rng = Pcg32::new(1, 0);
+        let mut objects: Vec<Box<dyn Hitable>> = Vec::new();
+        for _ in 0..100 {
+            objects.push(Box::new(Sphere {
+                center: random_vec3(&mut rng, 10.0),
+                radius: 0.2 + rng.next_f64(),
+                material: material.clone(),
+            }));
+            let corner = random_vec3(&mut rng, 10.0);
+            objects.push(Box::new(Triangle {
+                vertices: [
+                    &corner + &random_vec3(&mut rng, 2.0),
+                    &corner + &random_vec3(&mut rng, 2.0),
+                    corner,
+                ],
+                normals: None,
+                uvs: None,
+                material: material.clone(),
+            }));
+        }
+        objects
+    }
+
+    // a bvh has to find exactly the hits a plain list finds
+    #[test]
+    fn same_hits_as_a_list() {
+        let list = HitableList { hitables: objects() };
+        let bvh = BvhNode::new(objects()).unwrap();
+        let mut rng = Pcg32::new(2, 0);
+        let mut hits = 0;
+        for _ in 0..10000 {
+            let ray = Ray { start: random_vec3(&mut rng, 15.0), direction: random_vec3(&mut rng, 1.0) };
+            match (list.hit(&ray, 0.001, f64::INFINITY), bvh.hit(&ray, 0.001, f64::INFINITY)) {
+                (None, None) => {}
+                (Some(a), Some(b)) => {
+                    hits += 1;
+                    assert_eq!(a.time, b.time);
+                    assert_eq!((a.normal.x, a.normal.y, a.normal.z), (b.normal.x, b.normal.y, b.normal.z));
+                }
+                (a, b) => panic!("the list hit: {}, the bvh hit: {}", a.is_some(), b.is_some()),
+            }
+        }
+        // enough of them actually hit something to mean anything
+        assert!(hits > 1000, "{} hits", hits);
+    }
+
+    #[test]
+    fn nan_doesnt_break_the_build() {
+        let mut objects = objects();
+        let material = Arc::new(Lambertian {
+            albedo: Arc::new(SolidColor { color: Color { r: 0.5, g: 0.5, b: 0.5, a: 1.0 } }),
+        });
+        objects.push(Box::new(Sphere { center: Vec3 { x: f64::NAN, y: 0.0, z: 0.0 }, radius: 1.0, material }));
+        assert!(BvhNode::new(objects).is_some());
+    }
+}
@@ -0,0 +1,157 @@
+// an edge avoiding a-trous wavelet filter, see "edge-avoiding a-trous wavelet transform for fast
+// global illumination filtering" by dammertz, sewtz, hanika and lensch. every pass blurs with the
+// same 5x5 kernel but spreads its taps twice as far apart as the one before, and neighbours only
+// count as much as their normal, albedo and depth look like the pixel's own. how different their
+// color may be depends on how noisy the pixel still is, like in svgf (schied et al.), so the more
+// samples there are the less it blurs
+use rayon::prelude::*;
+
+use crate::features::Features;
+use crate::{dot, Color};
+
+const PASSES: u32 = 5;
+// the b3 spline, 1 4 6 4 1 over 16
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// how different neighbours can be before they stop counting, bigger blurs across more.
+// in standard deviations of the pixel's noise
+const SIGMA_LUMINANCE: f64 = 4.0;
+const SIGMA_NORMAL: f64 = 0.3;
+const SIGMA_ALBEDO: f64 = 0.1;
+// relative to the depth, per pixel of distance
+const SIGMA_DEPTH: f64 = 0.02;
+// the noise estimate from so few samples isn't worth much, the neighbours' colors give a better one
+const MIN_SAMPLES_FOR_VARIANCE: u32 = 4;
+
+// color, luminance_squared and features are averages per pixel over its samples, row by row
+pub(crate) fn denoise(width: usize, height: usize, samples: &[u32],
+                      color: &[Color], luminance_squared: &[f64], features: &[Features]) -> Vec<Color> {
+    let normals: Vec<[f64; 3]> = features.iter().map(|features| {
+        let normal = &features.normal;
+        let length = dot(normal, normal).sqrt();
+        if length > 0.0 {
+            [normal.x / length, normal.y / length, normal.z / length]
+        } else {
+            [0.0; 3]
+        }
+    }).collect();
+    // lights have albedos way above 1
+    let albedos: Vec<[f64; 3]> = features.iter()
+        .map(|features| compress([features.albedo.r, features.albedo.g, features.albedo.b]))
+        .collect();
+    let depths: Vec<f64> = features.iter().map(|features| features.depth).collect();
+
+    let mut current: Vec<[f64; 3]> = color.iter().map(|color| [color.r, color.g, color.b]).collect();
+    let luminances: Vec<f64> = current.iter().map(luminance).collect();
+    let spatial = if samples.iter().any(|&n| n < MIN_SAMPLES_FOR_VARIANCE) {
+        spatial_variance(width, height, &luminances)
+    } else {
+        Vec::new()
+    };
+    let variance: Vec<f64> = (0..luminances.len()).map(|p| {
+        if samples[p] >= MIN_SAMPLES_FOR_VARIANCE {
+            // of the average, which is the variance of single samples over their number
+            let mean = luminances[p];
+            (luminance_squared[p] - mean * mean).max(0.0) / samples[p] as f64
+        } else {
+            spatial[p]
+        }
+    }).collect();
+    let mut variance = blur_3x3(width, height, &variance);
+
+    for pass in 0..PASSES {
+        let step = 1 << pass;
+        let filtered: Vec<([f64; 3], f64)> = (0..current.len()).into_par_iter().map(|p| {
+            let (x, y) = ((p % width) as isize, (p / width) as isize);
+            let luminance_p = luminance(&current[p]);
+            let luminance_scale = SIGMA_LUMINANCE * variance[p].sqrt() + 1e-6;
+            let mut sum = [0.0; 3];
+            let mut variance_sum = 0.0;
+            let mut total = 0.0;
+            for (j, ky) in KERNEL.iter().enumerate() {
+                let qy = y + (j as isize - 2) * step;
+                if qy < 0 || qy >= height as isize {
+                    continue;
+                }
+                for (i, kx) in KERNEL.iter().enumerate() {
+                    let qx = x + (i as isize - 2) * step;
+                    if qx < 0 || qx >= width as isize {
+                        continue;
+                    }
+                    let q = qy as usize * width + qx as usize;
+
+                    let color = (luminance_p - luminance(&current[q])).abs() / luminance_scale;
+                    let normal = distance_squared(&normals[p], &normals[q]) / (SIGMA_NORMAL * SIGMA_NORMAL);
+                    let albedo = distance_squared(&albedos[p], &albedos[q]) / (SIGMA_ALBEDO * SIGMA_ALBEDO);
+                    let depth_scale = depths[p].max(depths[q]) * SIGMA_DEPTH * step as f64;
+                    let depth = if depth_scale > 0.0 { (depths[p] - depths[q]).abs() / depth_scale } else { 0.0 };
+
+                    let weight = kx * ky * libm::exp(-(color + normal + albedo + depth));
+                    for (sum, value) in sum.iter_mut().zip(current[q]) {
+                        *sum += weight * value;
+                    }
+                    variance_sum += weight * weight * variance[q];
+                    total += weight;
+                }
+            }
+            // the pixel itself always has a weight above 0
+            ([sum[0] / total, sum[1] / total, sum[2] / total], variance_sum / (total * total))
+        }).collect();
+
+        current = filtered.iter().map(|(color, _)| *color).collect();
+        variance = filtered.iter().map(|(_, variance)| *variance).collect();
+    }
+
+    current.into_iter().map(|[r, g, b]| Color { r, g, b, a: 1.0 }).collect()
+}
+
+// how much the luminance varies among the pixel and its neighbours, for when there aren't enough
+// samples to tell from the pixel alone
+fn spatial_variance(width: usize, height: usize, luminances: &[f64]) -> Vec<f64> {
+    (0..luminances.len()).map(|p| {
+        let (x, y) = (p % width, p / width);
+        let (mut sum, mut squared, mut n) = (0.0, 0.0, 0.0);
+        for qy in y.saturating_sub(2)..(y + 3).min(height) {
+            for qx in x.saturating_sub(2)..(x + 3).min(width) {
+                let luminance = luminances[qy * width + qx];
+                sum += luminance;
+                squared += luminance * luminance;
+                n += 1.0;
+            }
+        }
+        let mean = sum / n;
+        (squared / n - mean * mean).max(0.0)
+    }).collect()
+}
+
+// the variance estimates are noisy themselves
+fn blur_3x3(width: usize, height: usize, values: &[f64]) -> Vec<f64> {
+    const GAUSSIAN: [f64; 3] = [0.25, 0.5, 0.25];
+    (0..values.len()).map(|p| {
+        let (x, y) = ((p % width) as isize, (p / width) as isize);
+        let (mut sum, mut total) = (0.0, 0.0);
+        for (j, ky) in GAUSSIAN.iter().enumerate() {
+            for (i, kx) in GAUSSIAN.iter().enumerate() {
+                let (qx, qy) = (x + i as isize - 1, y + j as isize - 1);
+                if qx >= 0 && qx < width as isize && qy >= 0 && qy < height as isize {
+                    sum += kx * ky * values[qy as usize * width + qx as usize];
+                    total += kx * ky;
+                }
+            }
+        }
+        sum / total
+    }).collect()
+}
+
+fn luminance(color: &[f64; 3]) -> f64 {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
+// so lights don't make everything around them look like an edge
+fn compress(color: [f64; 3]) -> [f64; 3] {
+    color.map(|c| c / (1.0 + c.max(0.0)))
+}
+
+fn distance_squared(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
@@ -0,0 +1,171 @@
+// equirectangular pictures of everything around the scene, usually hdr so the sun can be thousands
+// of times brighter than the sky next to it. rays that miss everything take their light from it
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::texture::Image;
+use crate::sampler::Sampler;
+use crate::{Color, Vec3};
+
+pub(crate) struct EnvironmentMap {
+    image: Arc<Image>,
+    // around the y axis in radians, counterclockwise when looking down
+    rotation: f64,
+    // None unless directions get picked by brightness
+    distribution: Option<Distribution>,
+}
+
+impl EnvironmentMap {
+    pub(crate) fn new(image: Arc<Image>, rotation: f64, importance_sampling: bool) -> EnvironmentMap {
+        let distribution = if importance_sampling {
+            // rows near the poles get squeezed into less solid angle, so they count less
+            let (width, height) = (image.width as usize, image.height as usize);
+            let weights: Vec<f64> = image.pixels.iter().enumerate()
+                .map(|(i, pixel)| {
+                    let row = i / width;
+                    let sin_theta = libm::sin(PI * (row as f64 + 0.5) / height as f64);
+                    pixel.luminance() * sin_theta
+                })
+                .collect();
+            Distribution::new(&weights, width, height)
+        } else {
+            None
+        };
+
+        EnvironmentMap {
+            image,
+            rotation: rotation.to_radians(),
+            distribution,
+        }
+    }
+
+    pub(crate) fn is_sampled(&self) -> bool {
+        self.distribution.is_some()
+    }
+
+    pub(crate) fn color(&self, direction: &Vec3) -> Color {
+        let (col, row) = self.pixel(direction);
+        self.image.pixels[row * self.image.width as usize + col].clone()
+    }
+
+    // density per solid angle of random() picking direction
+    pub(crate) fn pdf_value(&self, direction: &Vec3) -> f64 {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return 0.0,
+        };
+        let (_, v) = self.uv(direction);
+        let sin_theta = libm::sin(PI * v);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (col, row) = self.pixel(direction);
+        let (width, height) = (self.image.width as f64, self.image.height as f64);
+        // every pixel covers 1 / (width * height) of the uv square, which gets wrapped around the
+        // sphere with a solid angle of 2 pi^2 sin(theta) per unit of uv area
+        let probability = distribution.probability(row * self.image.width as usize + col);
+        probability * width * height / (2.0 * PI * PI * sin_theta)
+    }
+
+    // a direction picked by brightness, only call this if it's sampled
+    pub(crate) fn random(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let distribution = self.distribution.as_ref().expect("the environment map isn't importance sampled");
+        let ((col, u), (row, v)) = distribution.sample(sampler);
+        let u = (col as f64 + u) / self.image.width as f64;
+        let v = (row as f64 + v) / self.image.height as f64;
+        self.direction(u, v)
+    }
+
+    // u goes around the y axis, v from straight up (0) to straight down (1)
+    fn uv(&self, direction: &Vec3) -> (f64, f64) {
+        let direction = direction.normalize();
+        let phi = libm::atan2(direction.z, direction.x) + self.rotation;
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        let v = libm::acos(direction.y.clamp(-1.0, 1.0)) / PI;
+        (u, v)
+    }
+
+    fn direction(&self, u: f64, v: f64) -> Vec3 {
+        let phi = 2.0 * PI * u - self.rotation;
+        let (sin_theta, cos_theta) = libm::sincos(PI * v);
+        Vec3 {
+            x: sin_theta * libm::cos(phi),
+            y: cos_theta,
+            z: sin_theta * libm::sin(phi),
+        }
+    }
+
+    fn pixel(&self, direction: &Vec3) -> (usize, usize) {
+        let (u, v) = self.uv(direction);
+        let (width, height) = (self.image.width as usize, self.image.height as usize);
+        let col = ((u * width as f64) as usize).min(width - 1);
+        let row = ((v * height as f64) as usize).min(height - 1);
+        (col, row)
+    }
+}
+
+// picks pixels with a probability proportional to their weight, first a row and then a column in it
+struct Distribution {
+    width: usize,
+    // cumulative over the rows, ends at 1
+    rows: Vec<f64>,
+    // cumulative within each row, each row ends at 1
+    columns: Vec<f64>,
+    probabilities: Vec<f64>,
+}
+
+impl Distribution {
+    // None if all the weights are 0
+    fn new(weights: &[f64], width: usize, height: usize) -> Option<Distribution> {
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 || !total.is_finite() {
+            return None;
+        }
+
+        let mut rows = Vec::with_capacity(height);
+        let mut columns = Vec::with_capacity(width * height);
+        let mut row_sum = 0.0;
+        for row in weights.chunks(width) {
+            let sum: f64 = row.iter().sum();
+            let mut col_sum = 0.0;
+            for (col, weight) in row.iter().enumerate() {
+                col_sum += weight;
+                // a black row never gets picked, but the numbers still have to make sense
+                columns.push(if sum > 0.0 { col_sum / sum } else { (col + 1) as f64 / width as f64 });
+            }
+            *columns.last_mut().unwrap() = 1.0;
+            row_sum += sum;
+            rows.push(row_sum / total);
+        }
+        *rows.last_mut().unwrap() = 1.0;
+
+        Some(Distribution {
+            width,
+            rows,
+            columns,
+            probabilities: weights.iter().map(|weight| weight / total).collect(),
+        })
+    }
+
+    fn probability(&self, pixel: usize) -> f64 {
+        self.probabilities[pixel]
+    }
+
+    // (col, where in the pixel horizontally), (row, where in the pixel vertically)
+    fn sample(&self, sampler: &mut dyn Sampler) -> ((usize, f64), (usize, f64)) {
+        let (r1, r2) = sampler.next_2d();
+        let (row, v) = pick(&self.rows, r1);
+        let (col, u) = pick(&self.columns[row * self.width..(row + 1) * self.width], r2);
+        ((col, u), (row, v))
+    }
+}
+
+// the first entry of the cumulative distribution that is above r, and how far into that entry's
+// share of [0, 1) r is. using that for the spot in the pixel keeps the samples as spread out as r is
+fn pick(cdf: &[f64], r: f64) -> (usize, f64) {
+    let i = cdf.partition_point(|&c| c <= r).min(cdf.len() - 1);
+    let start = if i == 0 { 0.0 } else { cdf[i - 1] };
+    let share = cdf[i] - start;
+    let offset = if share > 0.0 { ((r - start) / share).clamp(0.0, 1.0) } else { 0.5 };
+    (i, offset)
+}
@@ -0,0 +1,197 @@
+// linear pictures for compositing, the light as it came out of the renderer without tone mapping
+// and without buf's 8 bits. pfm is the simplest float format there is, openexr can carry the aovs
+// along as more channels. both are written by hand, uncompressed, since that's only a header
+// in front of the numbers
+pub(crate) enum Values {
+    Float(Vec<f32>),
+    Uint(Vec<u32>),
+}
+
+// one value per pixel, row by row from the top
+pub(crate) struct Channel {
+    pub(crate) name: String,
+    pub(crate) values: Values,
+}
+
+// rgb, three floats per pixel row by row from the top
+pub(crate) fn pfm(width: u32, height: u32, rgb: &[f32]) -> Vec<u8> {
+    // the negative scale says little endian
+    let mut bytes = format!("PF\n{} {}\n-1.0\n", width, height).into_bytes();
+    // pfm goes from the bottom row up
+    for row in rgb.chunks(3 * width as usize).rev() {
+        for value in row {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+// a single part scanline file, see "the openexr file layout" on openexr.com. names with a dot
+// in them are channels of a layer, like albedo.R
+pub(crate) fn exr(width: u32, height: u32, mut channels: Vec<Channel>) -> Vec<u8> {
+    // readers want them in alphabetical order
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // the magic number, then version 2 without any of the flags
+    let mut bytes = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+
+    let mut list = Vec::new();
+    for channel in channels.iter() {
+        list.extend_from_slice(channel.name.as_bytes());
+        list.push(0);
+        let pixel_type: i32 = match channel.values {
+            Values::Uint(_) => 0,
+            Values::Float(_) => 2,
+        };
+        list.extend_from_slice(&pixel_type.to_le_bytes());
+        // linear, three reserved bytes, then one sample per pixel in x and y
+        list.extend_from_slice(&[0, 0, 0, 0]);
+        list.extend_from_slice(&1i32.to_le_bytes());
+        list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    list.push(0);
+    attribute(&mut bytes, "channels", "chlist", &list);
+    // none
+    attribute(&mut bytes, "compression", "compression", &[0]);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    attribute(&mut bytes, "dataWindow", "box2i", &window);
+    attribute(&mut bytes, "displayWindow", "box2i", &window);
+    // from the top row down
+    attribute(&mut bytes, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut bytes, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut bytes, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut bytes, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    bytes.push(0);
+
+    // every row is a chunk of its own: its y, how many bytes follow, then the row of each channel
+    // one after the other. a table of where they start comes first
+    let row_size = 4 * width as usize * channels.len();
+    let first_row = bytes.len() + 8 * height as usize;
+    for y in 0..height as usize {
+        let offset = (first_row + y * (8 + row_size)) as u64;
+        bytes.extend_from_slice(&offset.to_le_bytes());
+    }
+    for y in 0..height as usize {
+        bytes.extend_from_slice(&(y as i32).to_le_bytes());
+        bytes.extend_from_slice(&(row_size as i32).to_le_bytes());
+        let row = y * width as usize..(y + 1) * width as usize;
+        for channel in channels.iter() {
+            match &channel.values {
+                Values::Float(values) => {
+                    for value in &values[row.clone()] {
+                        bytes.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+                Values::Uint(values) => {
+                    for value in &values[row.clone()] {
+                        bytes.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+            }
+        }
+    }
+    bytes
+}
+
+fn attribute(bytes: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    bytes.extend_from_slice(name.as_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(kind.as_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    bytes.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    fn read_u32(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    fn read_f32(bytes: &[u8], at: usize) -> f32 {
+        f32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    fn read_string(bytes: &[u8], at: &mut usize) -> String {
+        let end = *at + bytes[*at..].iter().position(|&b| b == 0).unwrap();
+        let string = String::from_utf8(bytes[*at..end].to_vec()).unwrap();
+        *at = end + 1;
+        string
+    }
+
+    #[test]
+    fn pfm_goes_bottom_up() {
+        let rgb: Vec<f32> = (0..12).map(|v| v as f32).collect();
+        let bytes = pfm(2, 2, &rgb);
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 4 * 12);
+        let values: Vec<f32> = (header.len()..bytes.len()).step_by(4).map(|at| read_f32(&bytes, at)).collect();
+        // the bottom row first, then the top one
+        assert_eq!(values, [6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn exr_layout() {
+        let (width, height) = (3, 2);
+        let float = |name: &str, value: f32| Channel {
+            name: name.to_string(),
+            values: Values::Float(vec![value; width * height]),
+        };
+        let channels = vec![
+            float("R", 1.0),
+            float("B", 3.0),
+            float("albedo.R", 4.0),
+            Channel { name: "id".to_string(), values: Values::Uint(vec![5; width * height]) },
+            float("G", 2.0),
+        ];
+        let bytes = exr(width as u32, height as u32, channels);
+        assert_eq!(&bytes[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+        // walk the attributes to find the end of the header and the channel list
+        let mut at = 8;
+        let mut names = Vec::new();
+        loop {
+            let name = read_string(&bytes, &mut at);
+            if name.is_empty() {
+                break;
+            }
+            let kind = read_string(&bytes, &mut at);
+            let size = read_u32(&bytes, at) as usize;
+            at += 4;
+            if name == "channels" {
+                assert_eq!(kind, "chlist");
+                let mut entry = at;
+                loop {
+                    let channel = read_string(&bytes, &mut entry);
+                    if channel.is_empty() {
+                        break;
+                    }
+                    names.push(channel);
+                    entry += 16;
+                }
+                assert_eq!(entry, at + size);
+            }
+            at += size;
+        }
+        let header = at;
+        assert_eq!(names, ["B", "G", "R", "albedo.R", "id"]);
+
+        let row_size = 4 * width * names.len();
+        assert_eq!(read_u32(&bytes, header) as usize, header + 8 * height);
+        assert_eq!(bytes.len(), header + 8 * height + height * (8 + row_size));
+
+        // the first row: its y, its size, then all of B, then all of G and so on
+        let row = header + 8 * height;
+        assert_eq!(read_u32(&bytes, row), 0);
+        assert_eq!(read_u32(&bytes, row + 4) as usize, row_size);
+        assert_eq!(read_f32(&bytes, row + 8), 3.0);
+        assert_eq!(read_u32(&bytes, row + 8 + row_size - 4), 5);
+    }
+}
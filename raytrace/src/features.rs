@@ -0,0 +1,70 @@
+// what the camera rays hit first, collected next to the color. noise hardly shows up in these, so
+// the denoiser can use them to tell real edges from noisy ones. they're also the aovs
+use crate::{Background, Color, HitRecord, Ray, Vec3};
+
+#[derive(Clone)]
+pub(crate) struct Features {
+    pub(crate) albedo: Color,
+    // unit length for a single sample, all 0 where the ray missed everything
+    pub(crate) normal: Vec3,
+    // distance from the camera, 0 where the ray missed everything
+    pub(crate) depth: f64,
+    // 1 if the ray hit something, summed up it's how many samples have a depth
+    pub(crate) hits: f64,
+    // ids don't add up, a sum keeps the ones of the first sample that hit something with an id.
+    // 0 for none
+    pub(crate) material_id: u32,
+    pub(crate) object_id: u32,
+}
+
+impl Features {
+    pub(crate) fn zero() -> Features {
+        Features {
+            albedo: Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 },
+            normal: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+            depth: 0.0,
+            hits: 0.0,
+            material_id: 0,
+            object_id: 0,
+        }
+    }
+
+    pub(crate) fn new(ray: &Ray, hitrecord: Option<&HitRecord>, background: &Background) -> Features {
+        match hitrecord {
+            Some(hitrecord) => Features {
+                albedo: hitrecord.material.albedo(hitrecord),
+                normal: hitrecord.normal.normalize(),
+                depth: hitrecord.time * ray.direction.length(),
+                hits: 1.0,
+                material_id: hitrecord.material.id(),
+                object_id: hitrecord.object_id,
+            },
+            None => Features {
+                albedo: background.color(&ray.direction),
+                ..Features::zero()
+            },
+        }
+    }
+
+    pub(crate) fn add(&mut self, other: &Features) {
+        self.albedo += other.albedo.clone();
+        self.normal = &self.normal + &other.normal;
+        self.depth += other.depth;
+        self.hits += other.hits;
+        if self.material_id == 0 && self.object_id == 0 {
+            self.material_id = other.material_id;
+            self.object_id = other.object_id;
+        }
+    }
+
+    pub(crate) fn scaled(&self, scale: f64) -> Features {
+        Features {
+            albedo: self.albedo.clone() * scale,
+            normal: scale * &self.normal,
+            depth: scale * self.depth,
+            hits: scale * self.hits,
+            material_id: self.material_id,
+            object_id: self.object_id,
+        }
+    }
+}
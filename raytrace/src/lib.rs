@@ -1,52 +1,163 @@
-// What is stratification? AA p19
-//
 extern crate console_error_panic_hook;
 
 use wasm_bindgen::prelude::*;
-use std::ops::{Mul, Div, DivAssign, Add, AddAssign, Sub};
-use rand::Rng;
+use std::ops::{Mul, Div, DivAssign, Add, AddAssign, Sub, Neg};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde::Deserialize;
+
+mod adaptive;
+mod aov;
+mod bvh;
+mod denoise;
+mod environment;
+mod export;
+mod features;
+mod mesh;
+mod noise;
+mod pdf;
+mod rng;
+mod sampler;
+mod scene;
+mod shapes;
+mod texture;
+mod tonemap;
+mod transform;
+mod wasm;
+
+use adaptive::Adaptive;
+use aov::{AovBuffers, NumberedObject};
+use bvh::Aabb;
+use environment::EnvironmentMap;
+use features::Features;
+use pdf::{CosinePdf, LightPdf, Pdf};
+use sampler::Sampler;
+use texture::{SolidColor, Texture};
+use tonemap::{Display, Transfer};
+
+pub use aov::Aov;
+pub use sampler::SamplerKind;
+pub use tonemap::ToneMap;
+pub use wasm::Canvas;
+#[cfg(all(target_arch = "wasm32", feature = "threads"))]
+pub use wasm_bindgen_rayon::init_thread_pool;
 
 struct Camera {
     origin: Vec3,
     lower_left_corner: Vec3,
     horizontal: Vec3,
     vertical: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    half_width: f64,
+    half_height: f64,
+    lens_radius: f64,
+    focus_dist: f64,
 }
 
 impl Camera {
-    fn calculate_ray(&self, u_right: f64, v_up: f64) -> Ray {
-        // borrow origin instead of making a clone! todo
-        Ray {
-            start: self.origin.clone(),
-            direction: &self.lower_left_corner + u_right * &self.horizontal + v_up * &self.vertical 
+    // vfov is the top to bottom field of view in degrees, aspect is width / height. anything
+    // that can't make a basis is an error, it would only give nan and a black picture
+    fn new(look_from: Vec3, look_at: &Vec3, vup: &Vec3, vfov: f64, aspect: f64) -> Result<Camera, String> {
+        if vfov.is_nan() || vfov <= 0.0 || vfov >= 180.0 {
+            return Err("the field of view has to be between 0 and 180 degrees".to_string());
+        }
+        let half_height = libm::tan(vfov.to_radians() / 2.0);
+        let half_width = aspect * half_height;
+
+        // orthonormal basis, w points backwards because the camera looks down -w
+        let backwards = &look_from - look_at;
+        if backwards.length() == 0.0 || backwards.length().is_nan() {
+            return Err("look_from and look_at have to be different points".to_string());
         }
+        let w = backwards.normalize();
+        let side = cross(vup, &w);
+        if side.length() == 0.0 || side.length().is_nan() {
+            return Err("vup can't be 0 or point along the view direction".to_string());
+        }
+        let u = side.normalize();
+        let v = cross(&w, &u);
+
+        let mut camera = Camera {
+            lower_left_corner: look_from.clone(),
+            horizontal: u.clone(),
+            vertical: v.clone(),
+            origin: look_from,
+            u,
+            v,
+            w,
+            half_width,
+            half_height,
+            lens_radius: 0.0,
+            focus_dist: 1.0,
+        };
+        // no aperture is a pinhole camera, then the focus distance doesn't matter
+        camera.set_lens(0.0, 1.0);
+        Ok(camera)
     }
-}
 
-impl Default for Camera {
-    fn default() -> Self {
-        Camera {
-            origin : Vec3 { x: 0.0, y: 0.0, z: 0.0 },
-            lower_left_corner : Vec3 { x: -2.0, y: -1.0, z: -1.0 },
-            horizontal : Vec3 { x: 4.0, y: 0.0, z: 0.0 },
-            vertical : Vec3 { x: 0.0, y: 2.0, z: 0.0 },
+    // width / height, for when the picture changes size
+    fn set_aspect(&mut self, aspect: f64) {
+        self.half_width = aspect * self.half_height;
+        self.set_lens(2.0 * self.lens_radius, self.focus_dist);
+    }
+
+    // a focus distance of 0 would squash the image plane into a point
+    fn check_lens(aperture: f64, focus_dist: f64) -> Result<(), String> {
+        if aperture.is_nan() || aperture < 0.0 {
+            return Err("the aperture can't be below 0".to_string());
+        }
+        if focus_dist.is_nan() || focus_dist <= 0.0 {
+            return Err("the focus distance has to be above 0".to_string());
+        }
+        Ok(())
+    }
+
+    // the image plane sits at the focus distance, that's where things are sharp
+    fn set_lens(&mut self, aperture: f64, focus_dist: f64) {
+        self.lens_radius = aperture / 2.0;
+        self.focus_dist = focus_dist;
+        self.lower_left_corner = &self.origin
+            - self.half_width * focus_dist * &self.u
+            - self.half_height * focus_dist * &self.v
+            - focus_dist * &self.w;
+        self.horizontal = 2.0 * self.half_width * focus_dist * &self.u;
+        self.vertical = 2.0 * self.half_height * focus_dist * &self.v;
+    }
+
+    fn calculate_ray(&self, u_right: f64, v_up: f64, sampler: &mut dyn Sampler) -> Ray {
+        // start somewhere on the lens instead of a single point
+        let rd = self.lens_radius * random_in_unit_disk(sampler);
+        let offset = rd.x * &self.u + rd.y * &self.v;
+        let start = &self.origin + offset;
+        Ray {
+            direction: &self.lower_left_corner + u_right * &self.horizontal + v_up * &self.vertical - &start,
+            start,
         }
     }
 }
 
-struct HitableList<'a> {
-    hitables: Vec<&'a Hitable>,
+struct HitableList {
+    hitables: Vec<Box<dyn Hitable>>,
 }
 
 struct HitRecord<'a> {
     time: f64,
     point: Vec3,
     normal: Vec3,
-    material: &'a Material,
+    // texture coordinates of the point on the surface
+    u: f64,
+    v: f64,
+    material: &'a dyn Material,
+    // which of the scene's objects this is part of, the shapes leave it at 0 and
+    // aov::NumberedObject fills it in
+    object_id: u32,
 }
 
-impl<'a> Hitable for HitableList<'a> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+impl Hitable for HitableList {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut result: Option<HitRecord> = None;
         let mut closest = t_max;
 
@@ -59,20 +170,56 @@ impl<'a> Hitable for HitableList<'a> {
 
         result
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut hitables = self.hitables.iter();
+        let mut bbox = hitables.next()?.bounding_box()?;
+        for hitable in hitables {
+            bbox = bbox.union(&hitable.bounding_box()?);
+        }
+        Some(bbox)
+    }
+
+    // as lights every one of them gets picked equally often
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        if self.hitables.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.hitables.iter().map(|hitable| hitable.pdf_value(origin, direction)).sum();
+        sum / self.hitables.len() as f64
+    }
+
+    fn random(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let n = self.hitables.len();
+        let i = ((sampler.next_1d() * n as f64) as usize).min(n - 1);
+        self.hitables[i].random(origin, sampler)
+    }
 }
 
-trait Hitable {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+trait Hitable: Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+    // None for things that go on forever
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    // only needed for shapes that can be lights: the density per solid angle of the directions
+    // from origin that hit the shape, and a random one of those directions
+    fn pdf_value(&self, _origin: &Vec3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn random(&self, _origin: &Vec3, _sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3 { x: 1.0, y: 0.0, z: 0.0 }
+    }
 }
 
-struct Sphere<'a> {
+struct Sphere {
     center: Vec3,
     radius: f64,
-    material: &'a Material,
+    material: Arc<dyn Material>,
 }
 
-impl<'a> Hitable for Sphere<'a> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+impl Hitable for Sphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc: Vec3 = &ray.start - &self.center;
         let a: f64 = dot(&ray.direction, &ray.direction);
         let b: f64 = dot(&oc, &ray.direction);
@@ -83,32 +230,98 @@ impl<'a> Hitable for Sphere<'a> {
             let temp: f64 = (- b - discriminant.sqrt()) / a;
             if temp < t_max && temp > t_min {
                 let point = ray.eval(temp);
+                let (u, v) = sphere_uv(&((&point - &self.center) / self.radius.abs()));
                 return Some(HitRecord {
                     time: temp,
                     // todo wtf...the order matters?
                     normal: (&point - &self.center) / self.radius,
                     point,
-                    material: self.material,
+                    u,
+                    v,
+                    material: &*self.material,
+                    object_id: 0,
                 })
             }
 
             let temp: f64 = (- b + discriminant.sqrt()) / a;
             if temp < t_max && temp > t_min {
                 let p = ray.eval(temp);
+                let (u, v) = sphere_uv(&((&p - &self.center) / self.radius.abs()));
                 return Some(HitRecord {
                     time: temp,
                     normal: (&p - &self.center) / self.radius,
                     point: p,
-                    material: self.material,
+                    u,
+                    v,
+                    material: &*self.material,
+                    object_id: 0,
                 })
             }
         }
 
         None
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // abs because hollow spheres have a negative radius
+        let r = self.radius.abs();
+        Some(Aabb {
+            min: &self.center - &Vec3 { x: r, y: r, z: r },
+            max: &self.center + &Vec3 { x: r, y: r, z: r },
+        })
+    }
+
+    // uniform over the cone of directions the sphere covers as seen from origin
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        let ray = Ray { start: origin.clone(), direction: direction.clone() };
+        if self.hit(&ray, 0.001, 99999999.0).is_none() {
+            return 0.0;
+        }
+        match self.cos_theta_max(origin) {
+            Some(cos_theta_max) => 1.0 / (2.0 * std::f64::consts::PI * (1.0 - cos_theta_max)),
+            // from the inside every direction hits
+            None => 1.0 / (4.0 * std::f64::consts::PI),
+        }
+    }
+
+    fn random(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let cos_theta_max = match self.cos_theta_max(origin) {
+            Some(cos_theta_max) => cos_theta_max,
+            None => return pdf::random_unit_vector(sampler),
+        };
+        let axis = (&self.center - origin).normalize();
+        let (tangent, bitangent) = shapes::tangents(&axis);
+        let (r1, r2) = sampler.next_2d();
+        let z = 1.0 + r1 * (cos_theta_max - 1.0);
+        let phi = 2.0 * std::f64::consts::PI * r2;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        r * libm::cos(phi) * &tangent + r * libm::sin(phi) * &bitangent + z * &axis
+    }
+}
+
+impl Sphere {
+    // half the opening angle of the cone around the sphere seen from origin, None from inside
+    fn cos_theta_max(&self, origin: &Vec3) -> Option<f64> {
+        let to_center = &self.center - origin;
+        let distance_squared = dot(&to_center, &to_center);
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return None;
+        }
+        Some((1.0 - radius_squared / distance_squared).sqrt())
+    }
 }
 
-//// todo i need testing for these fuckers...how do i set it up?
+// longitude and latitude of a point on the unit sphere, both mapped to [0, 1]
+fn sphere_uv(p: &Vec3) -> (f64, f64) {
+    let phi = libm::atan2(p.z, p.x);
+    let theta = libm::asin(p.y);
+    let u = 1.0 - (phi + std::f64::consts::PI) / (2.0 * std::f64::consts::PI);
+    let v = (theta + std::f64::consts::FRAC_PI_2) / std::f64::consts::PI;
+    (u, v)
+}
+
+// todo i need testing for these fuckers...how do i set it up?
 //    // todo the way i set these up is probably a bad idea...they all create new objects
 //    // that might be inefficient in many cases
 #[derive(Clone)]
@@ -126,6 +339,15 @@ impl Vec3 {
     fn length(&self) -> f64 {
         (self.x.powi(2) + self.y.powi(2) + self.z.powi(2)).sqrt()
     }
+
+    // 0 is x, 1 is y, 2 is z
+    fn axis(&self, axis: usize) -> f64 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
 }
 
 impl Mul<f64> for Vec3 {
@@ -220,6 +442,18 @@ impl Add<Vec3> for &Vec3 {
     }
 }
 
+impl Sub for Vec3 {
+    type Output = Self;
+
+    fn sub(self, other: Vec3) -> Self::Output {
+        Self::Output {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
+    }
+}
+
 impl Sub<Vec3> for &Vec3 {
     type Output = Vec3;
 
@@ -256,6 +490,18 @@ impl Sub<&Vec3> for &Vec3 {
     }
 }
 
+impl Neg for &Vec3 {
+    type Output = Vec3;
+
+    fn neg(self) -> Self::Output {
+        Self::Output {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Color {
     r: f64,
@@ -264,25 +510,10 @@ pub struct Color {
     a: f64,
 }
 
-// todo repetitive
 impl Color {
-    fn ri(&self) -> u8 {
-        (self.r*255.99) as u8
-    }
-    fn gi(&self) -> u8 {
-        (self.g*255.99) as u8
-    }
-    fn bi(&self) -> u8 {
-        (self.b*255.99) as u8
-    }
-    fn ai(&self) -> u8 {
-        (self.a*255.99) as u8
-    }
-    fn bytes(&self) -> [u8; 4] {
-        [self.ri(),
-         self.gi(),
-         self.bi(),
-         self.ai()]
+    // how bright it looks, green counts the most
+    fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
     }
 }
 
@@ -365,105 +596,736 @@ impl Ray {
         &self.start + t*&self.direction
     }
 
-    fn get_color(&self, world: &Hitable, depth: u8) -> Color {
-        // todo: setting the minimum to 0.001 is supposed to prevent shadow acne O_o
-        // the maximum ought to be something like MAX_FLOAT whatever it's called in rust
-        if let Some(hitrecord) = world.hit(&self, 0.001, 99999999.0) {
-            if depth < 50 {
-                if let Some((attenuation, scattered)) = hitrecord.material.scatter(&self, &hitrecord) {
-                    let mut color = attenuation * scattered.get_color(world, depth+1);
-                    color.a = 1.0;
+    // follows the ray from bounce to bounce and adds up the light that comes back along the way.
+    // hitrecord is whatever the ray hits first, the camera rays already know that. a loop rather
+    // than recursion so long paths can't run out of stack in wasm
+    fn trace(self, hitrecord: Option<HitRecord>, world: &dyn Hitable, lights: &HitableList,
+             background: &Background, max_depth: u32, sampler: &mut dyn Sampler) -> Color {
+        let mut ray = self;
+        let mut hitrecord = hitrecord;
+        let mut color = Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0 };
+        // how much of the light found further down the path makes it back to the camera
+        let mut throughput = Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 };
+        // the density the material picked this ray's direction with, None for camera rays and
+        // mirror like bounces. lights that such a ray hits only count partly because the rest of
+        // their light comes from aiming at them directly (multiple importance sampling)
+        let mut bsdf_pdf: Option<f64> = None;
+        let mut depth = 0;
+
+        loop {
+            let hit = match hitrecord {
+                Some(hit) => hit,
+                None => {
+                    let mut light = background.color(&ray.direction);
+                    if let Some(bsdf_pdf) = bsdf_pdf {
+                        let light_pdf = LightPdf::new(lights, background, &ray.start);
+                        light = power_heuristic(bsdf_pdf, light_pdf.value(&ray.direction)) * light;
+                    }
+                    color += throughput * light;
+                    break;
+                }
+            };
+
+            let mut emitted = hit.material.emitted(hit.u, hit.v, &hit.point);
+            if let Some(bsdf_pdf) = bsdf_pdf {
+                let light_pdf = LightPdf::new(lights, background, &ray.start);
+                emitted = power_heuristic(bsdf_pdf, light_pdf.value(&ray.direction)) * emitted;
+            }
+            color += throughput.clone() * emitted;
+
+            if depth >= max_depth {
+                break;
+            }
+            // russian roulette: dim paths stop at random, the ones that go on count for more so
+            // on average nothing gets lost. the first few bounces always happen, they matter most
+            if depth >= ROULETTE_DEPTH {
+                let survival = throughput.r.max(throughput.g).max(throughput.b).min(1.0);
+                if sampler.next_1d() >= survival {
+                    break;
+                }
+                throughput = throughput * (1.0 / survival);
+            }
 
-                    return color;
+            match hit.material.scatter(&ray, &hit, sampler) {
+                Some(Scatter::Specular { attenuation, ray: scattered }) => {
+                    throughput = throughput * attenuation;
+                    bsdf_pdf = None;
+                    ray = scattered;
+                }
+                Some(Scatter::Diffuse { attenuation, pdf }) => {
+                    // a shadow ray towards a random point on one of the lights
+                    let light_pdf = LightPdf::new(lights, background, &hit.point);
+                    if !light_pdf.is_empty() {
+                        let to_light = Ray { start: hit.point.clone(), direction: light_pdf.generate(sampler) };
+                        let light_density = light_pdf.value(&to_light.direction);
+                        let scattering = hit.material.scattering_pdf(&ray, &hit, &to_light);
+                        if light_density > 0.0 && scattering > 0.0 {
+                            let weight = power_heuristic(light_density, pdf.value(&to_light.direction));
+                            let light = to_light.direct_light(world, background);
+                            color += (weight * scattering / light_density) * throughput.clone() * attenuation.clone() * light;
+                        }
+                    }
+
+                    // and a bounce wherever the material sends it
+                    let scattered = Ray { start: hit.point.clone(), direction: pdf.generate(sampler) };
+                    let density = pdf.value(&scattered.direction);
+                    let scattering = hit.material.scattering_pdf(&ray, &hit, &scattered);
+                    if density <= 0.0 || scattering <= 0.0 {
+                        break;
+                    }
+                    throughput = (scattering / density) * throughput * attenuation;
+                    bsdf_pdf = Some(density);
+                    ray = scattered;
                 }
+                None => break,
             }
 
-            Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 }
-        } else {
-            let unit_direction = self.direction.normalize();
-            let t = 0.5 * (unit_direction.y + 1.0);
+            // todo: setting the minimum to 0.001 is supposed to prevent shadow acne O_o
+            // the maximum ought to be something like MAX_FLOAT whatever it's called in rust
+            hitrecord = world.hit(&ray, 0.001, 99999999.0);
+            depth += 1;
+        }
 
-            let mut color = (1.0 - t)*Color{r: 1.0, g: 1.0, b: 1.0, a: 1.0} + t*Color{r: 0.5, g: 0.7, b: 1.0, a: 1.0};
-            color.a = 1.0;
+        color.a = 1.0;
+        color
+    }
 
-            color
+    // the light at the first thing the ray hits, without bouncing any further
+    fn direct_light(&self, world: &dyn Hitable, background: &Background) -> Color {
+        match world.hit(self, 0.001, 99999999.0) {
+            Some(hitrecord) => hitrecord.material.emitted(hitrecord.u, hitrecord.v, &hitrecord.point),
+            None => background.color(&self.direction),
         }
     }
 }
 
-trait Material {
-    // todo can i add names for the parts of the return value?
-    fn scatter(&self, ray: &Ray, hitrecord: &HitRecord) -> Option<(Color, Ray)>;
+// bounces before russian roulette can end a path
+const ROULETTE_DEPTH: u32 = 3;
+
+// how much of a sample to keep when two strategies could have produced it, the one with the
+// higher density gets most of it
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    a / (a + b)
+}
+
+// what rays see when they don't hit anything
+enum Background {
+    // the white to light blue gradient
+    Sky,
+    // black for scenes that are only lit by lights
+    Solid(Color),
+    Environment(EnvironmentMap),
+}
+
+impl Background {
+    fn color(&self, direction: &Vec3) -> Color {
+        match self {
+            Background::Sky => {
+                let unit_direction = direction.normalize();
+                let t = 0.5 * (unit_direction.y + 1.0);
+
+                let mut color = (1.0 - t)*Color{r: 1.0, g: 1.0, b: 1.0, a: 1.0} + t*Color{r: 0.5, g: 0.7, b: 1.0, a: 1.0};
+                color.a = 1.0;
+
+                color
+            }
+            Background::Solid(color) => color.clone(),
+            Background::Environment(environment) => environment.color(direction),
+        }
+    }
+}
+
+enum Scatter {
+    // mirrors and glass, there is only one way to go so aiming at lights is pointless
+    Specular { attenuation: Color, ray: Ray },
+    // the direction comes from pdf, the color gets scaled by scattering_pdf over pdf
+    Diffuse { attenuation: Color, pdf: Box<dyn Pdf> },
+}
+
+trait Material: Send + Sync {
+    // None if the light gets absorbed
+    fn scatter(&self, ray: &Ray, hitrecord: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter>;
+
+    // the density of light bouncing from ray into scattered's direction, per solid angle.
+    // only diffuse materials need it
+    fn scattering_pdf(&self, _ray: &Ray, _hitrecord: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    // light given off by the surface itself, only lights have any
+    fn emitted(&self, _u: f64, _v: f64, _point: &Vec3) -> Color {
+        Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0 }
+    }
+
+    // the surface's own color without any lighting, for the denoiser. white for clear materials
+    fn albedo(&self, _hitrecord: &HitRecord) -> Color {
+        Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 }
+    }
+
+    // for the material id aov, see aov::NumberedMaterial
+    fn id(&self) -> u32 {
+        0
+    }
+}
+
+// glowing surface, the color can go above 1 for brighter lights
+struct DiffuseLight {
+    emit: Arc<dyn Texture>,
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray: &Ray, _hitrecord: &HitRecord, _sampler: &mut dyn Sampler) -> Option<Scatter> {
+        None
+    }
+
+    fn emitted(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        self.emit.value(u, v, point)
+    }
+
+    fn albedo(&self, hitrecord: &HitRecord) -> Color {
+        self.emit.value(hitrecord.u, hitrecord.v, &hitrecord.point)
+    }
 }
 
 struct Lambertian {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, hitrecord: &HitRecord) -> Option<(Color, Ray)> {
-        let target = &hitrecord.point + &hitrecord.normal + random_in_unit_sphere();
-        let scattered = Ray {
-            direction: target - &hitrecord.point,
-            start: hitrecord.point.clone(),
-        };
+    fn scatter(&self, _ray: &Ray, hitrecord: &HitRecord, _sampler: &mut dyn Sampler) -> Option<Scatter> {
+        Some(Scatter::Diffuse {
+            attenuation: self.albedo.value(hitrecord.u, hitrecord.v, &hitrecord.point),
+            pdf: Box::new(CosinePdf::new(&hitrecord.normal)),
+        })
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, hitrecord: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = dot(&hitrecord.normal.normalize(), &scattered.direction.normalize());
+        cosine.max(0.0) / std::f64::consts::PI
+    }
 
-        Some((self.albedo.clone() , scattered)) // todo: clones make me nervous...funny given that all my basic operators create clones x)
+    fn albedo(&self, hitrecord: &HitRecord) -> Color {
+        self.albedo.value(hitrecord.u, hitrecord.v, &hitrecord.point)
     }
 }
 
 struct Metal {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
     fuzz: f64,
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hitrecord: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, ray: &Ray, hitrecord: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
         let reflected = reflect(&ray.direction.normalize(), &hitrecord.normal);
         let scattered = Ray {
-            direction: reflected + self.fuzz*random_in_unit_sphere(),
+            direction: reflected + self.fuzz*random_in_unit_sphere(sampler),
             start: hitrecord.point.clone(),
         };
         if dot(&scattered.direction, &hitrecord.normal) > 0.0 {
-            Some((self.albedo.clone() , scattered))
+            Some(Scatter::Specular {
+                attenuation: self.albedo.value(hitrecord.u, hitrecord.v, &hitrecord.point),
+                ray: scattered,
+            })
         } else {
             None
         }
     }
+
+    fn albedo(&self, hitrecord: &HitRecord) -> Color {
+        self.albedo.value(hitrecord.u, hitrecord.v, &hitrecord.point)
+    }
+}
+
+// glass, water etc. a hollow sphere is a sphere with a second one of negative radius
+// inside it, which flips the normals so the ray knows it's leaving the material
+struct Dielectric {
+    ref_idx: f64,
+}
+
+impl Material for Dielectric {
+    fn scatter(&self, ray: &Ray, hitrecord: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let attenuation = Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 };
+        let cosine = dot(&ray.direction, &hitrecord.normal) / ray.direction.length();
+
+        // the normal always points out of the surface, so a positive dot means we're inside
+        let (outward_normal, ni_over_nt, cosine) = if cosine > 0.0 {
+            (-&hitrecord.normal, self.ref_idx, self.ref_idx * cosine)
+        } else {
+            (hitrecord.normal.clone(), 1.0 / self.ref_idx, -cosine)
+        };
+
+        let direction = match refract(&ray.direction, &outward_normal, ni_over_nt) {
+            Some(refracted) if sampler.next_1d() >= schlick(cosine, self.ref_idx) => refracted,
+            // total internal reflection or schlick picked the reflection
+            _ => reflect(&ray.direction, &hitrecord.normal),
+        };
+
+        Some(Scatter::Specular {
+            attenuation,
+            ray: Ray {
+                direction,
+                start: hitrecord.point.clone(),
+            },
+        })
+    }
 }
 
 fn dot(a: &Vec3, b: &Vec3) -> f64 {
     a.x*b.x + a.y*b.y + a.z*b.z
 }
 
+fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
+    Vec3 {
+        x: a.y*b.z - a.z*b.y,
+        y: a.z*b.x - a.x*b.z,
+        z: a.x*b.y - a.y*b.x,
+    }
+}
+
 fn reflect(v: &Vec3, normal: &Vec3) -> Vec3 {
     v - 2.0*dot(v,normal)*normal
 }
 
-fn random_in_unit_sphere() -> Vec3 {
-    loop {
-        let p = 2.0 * Vec3 {
-            x: rand::thread_rng().gen::<f64>(),
-            y: rand::thread_rng().gen::<f64>(),
-            z: rand::thread_rng().gen::<f64>(),
-        } - &Vec3 { x: 1.0, y: 1.0, z: 1.0 };
-        if p.length() < 1.0 {
-            return p;
-        }
+// snell's law, None if there is total internal reflection
+fn refract(v: &Vec3, normal: &Vec3, ni_over_nt: f64) -> Option<Vec3> {
+    let uv = v.normalize();
+    let dt = dot(&uv, normal);
+    let discriminant = 1.0 - ni_over_nt.powi(2) * (1.0 - dt.powi(2));
+    if discriminant > 0.0 {
+        Some(ni_over_nt * (uv - dt * normal) - discriminant.sqrt() * normal)
+    } else {
+        None
     }
 }
 
+// schlick's approximation of how much light gets reflected depending on the angle
+fn schlick(cosine: f64, ref_idx: f64) -> f64 {
+    let r0 = ((1.0 - ref_idx) / (1.0 + ref_idx)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+}
+
+// no rejection sampling in these two, a sample has to use the same number of dimensions every time
+// or the ones after it get out of step
+fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.next_2d();
+    // the square root keeps the points from bunching up in the middle
+    let r = r1.sqrt();
+    let (sin_phi, cos_phi) = libm::sincos(2.0 * std::f64::consts::PI * r2);
+    Vec3 { x: r * cos_phi, y: r * sin_phi, z: 0.0 }
+}
+
+fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let direction = pdf::random_unit_vector(sampler);
+    libm::cbrt(sampler.next_1d()) * direction
+}
+
+const TILE_SIZE: u32 = 16;
+
+// the order render_for works through the tiles in
 #[wasm_bindgen]
-pub struct Canvas {
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TileOrder {
+    Scanline,
+    // from the center outwards, the interesting stuff is usually in the middle
+    Spiral,
+    // neighbouring tiles get rendered close together in time
+    Hilbert,
+}
+
+#[derive(Clone, Copy)]
+struct Tile {
+    col_start: u32,
+    col_end: u32,
+    row_start: u32,
+    row_end: u32,
+}
+
+fn make_tiles(width: u32, height: u32, order: TileOrder) -> Vec<Tile> {
+    let tiles_x = width.div_ceil(TILE_SIZE);
+    let tiles_y = height.div_ceil(TILE_SIZE);
+
+    // positions in the grid of tiles, not pixels
+    let mut positions: Vec<(u32, u32)> = Vec::new();
+    match order {
+        TileOrder::Scanline => {
+            for ty in 0..tiles_y {
+                for tx in 0..tiles_x {
+                    positions.push((tx, ty));
+                }
+            }
+        }
+        TileOrder::Spiral => {
+            for ty in 0..tiles_y {
+                for tx in 0..tiles_x {
+                    positions.push((tx, ty));
+                }
+            }
+            // sorting by ring and then by angle within the ring walks around in a spiral
+            let cx = (tiles_x as f64 - 1.0) / 2.0;
+            let cy = (tiles_y as f64 - 1.0) / 2.0;
+            let key = |&(tx, ty): &(u32, u32)| {
+                let dx = tx as f64 - cx;
+                let dy = ty as f64 - cy;
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            positions.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            // the curve only covers power of two squares so skip whatever is outside the image
+            let n = tiles_x.max(tiles_y).next_power_of_two();
+            for d in 0..n * n {
+                let (tx, ty) = hilbert_d2xy(n, d);
+                if tx < tiles_x && ty < tiles_y {
+                    positions.push((tx, ty));
+                }
+            }
+        }
+    }
+
+    positions.iter().map(|&(tx, ty)| Tile {
+        col_start: tx * TILE_SIZE,
+        col_end: ((tx + 1) * TILE_SIZE).min(width),
+        row_start: ty * TILE_SIZE,
+        row_end: ((ty + 1) * TILE_SIZE).min(height),
+    }).collect()
+}
+
+// distance along the hilbert curve to position in an n by n grid
+fn hilbert_d2xy(n: u32, d: u32) -> (u32, u32) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+// std::time panics on wasm32-unknown-unknown so only use it natively
+#[cfg(not(target_arch = "wasm32"))]
+fn now_ms() -> f64 {
+    let since_epoch = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    since_epoch.as_secs_f64() * 1000.0
+}
+
+// what pixel_color adds up over the samples of a pixel
+struct PixelSum {
+    color: Color,
+    luminance_squared: f64,
+    features: Features,
+}
+
+// everything needed to render a scene, without anything browser specific. the wasm Canvas and
+// the command line renderer are both built on top of it
+pub struct Renderer {
     width: u32,
     height: u32,
     buf: Vec<u8>,
+    camera: Camera,
+    world: HitableList,
+    // copies of the emitting spheres and rects in world, for aiming rays at them
+    lights: HitableList,
+    background: Background,
+    // decoded pictures for image textures
+    images: HashMap<String, Arc<texture::Image>>,
+    // meshes for mesh objects in scenes
+    meshes: HashMap<String, mesh::Mesh>,
+    // running sum of all samples per pixel, buf shows accum / pixel_samples
+    accum: Vec<Color>,
+    // running sum of the squared luminance of all samples, for how noisy a pixel still is
+    accum_squared: Vec<f64>,
+    // the same for what the camera rays hit first
+    features: Vec<Features>,
+    // how many samples the sums of each pixel are over. they differ with adaptive sampling, and
+    // while render_for is busy
+    pixel_samples: Vec<u32>,
+    // how many samples per pixel have been asked for, on average with adaptive sampling
+    samples: u32,
+    samples_per_pixel: u32,
+    // None gives every pixel the same number of samples
+    adaptive: Option<Adaptive>,
+    // bounces per path at most
+    max_depth: u32,
+    tiles: Vec<Tile>,
+    // where render_for picks up again
+    next_tile: usize,
+    // the samples of the round render_for is on, by tile
+    round: Vec<Vec<(usize, u32)>>,
+    // how many rounds have been planned since the last reset
+    rounds: u32,
+    // the samples adaptive sampling still has to hand out
+    budget: u64,
+    seed: u64,
+    sampler: SamplerKind,
+    tile_order: TileOrder,
+    // how accum turns into buf
+    display: Display,
+    // whether buf shows accum filtered by the denoiser
+    denoise: bool,
+    // what buf shows
+    view: Aov,
+    aov_buffers: AovBuffers,
+    // how many objects have an object id
+    objects: u32,
 }
 
-#[wasm_bindgen]
-impl Canvas {
-    pub fn buf(&self) -> *const u8 {
-        self.buf.as_ptr()
+impl Renderer {
+    fn from_scene(scene: scene::Scene) -> Renderer {
+        let settings = scene.settings;
+        let (width, height) = (settings.width, settings.height);
+        Renderer {
+            width,
+            height,
+            buf: vec![0; (width * height) as usize * 4],
+            camera: scene.camera,
+            world: scene.world,
+            lights: scene.lights,
+            background: scene.background,
+            images: HashMap::new(),
+            meshes: HashMap::new(),
+            accum: vec![Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 }; (width * height) as usize],
+            accum_squared: vec![0.0; (width * height) as usize],
+            features: vec![Features::zero(); (width * height) as usize],
+            pixel_samples: vec![0; (width * height) as usize],
+            samples: 0,
+            samples_per_pixel: settings.samples_per_pixel,
+            adaptive: settings.adaptive,
+            max_depth: settings.max_depth,
+            tiles: make_tiles(width, height, settings.tile_order),
+            next_tile: 0,
+            round: Vec::new(),
+            rounds: 0,
+            budget: 0,
+            seed: settings.seed,
+            sampler: settings.sampler,
+            tile_order: settings.tile_order,
+            display: Display {
+                tone_map: settings.tone_map,
+                exposure: settings.exposure,
+                transfer: match settings.gamma {
+                    Some(gamma) => Transfer::Gamma(gamma),
+                    None => Transfer::Srgb,
+                },
+            },
+            denoise: settings.denoise,
+            view: Aov::Beauty,
+            aov_buffers: AovBuffers::default(),
+            objects: scene.objects,
+        }
+    }
+
+    fn sample_pixel(&self, row: u32, col: u32, sampler: &mut dyn Sampler) -> (Color, Features) {
+        // some sampling for antialiasing
+        let (u_offset, v_offset) = sampler.next_2d();
+        let u_right: f64 = (col as f64 + u_offset) / (self.width as f64);
+        let v_up: f64 = ((self.height - row) as f64 + v_offset) / (self.height as f64);
+        let ray = self.camera.calculate_ray(u_right, v_up, sampler);
+
+        let hitrecord = self.world.hit(&ray, 0.001, 99999999.0);
+        let features = Features::new(&ray, hitrecord.as_ref(), &self.background);
+        let color = ray.trace(hitrecord, &self.world, &self.lights, &self.background, self.max_depth, sampler);
+        (color, features)
+    }
+
+    // sums up n samples for pixel i, starting at sample number first_sample
+    fn pixel_color(&self, i: usize, first_sample: u32, n: u32) -> PixelSum {
+        let row = i as u32 / self.width;
+        let col = i as u32 % self.width;
+        let mut sum = PixelSum {
+            color: Color{ r: 0.0, g: 0.0, b: 0.0, a: 0.0 },
+            luminance_squared: 0.0,
+            features: Features::zero(),
+        };
+        let mut sampler = sampler::make_sampler(self.sampler, self.seed, self.samples_per_pixel);
+        for s in first_sample..first_sample + n {
+            sampler.start_sample(i, s);
+            let (color, features) = self.sample_pixel(row, col, &mut *sampler);
+            sum.luminance_squared += color.luminance().powi(2);
+            sum.color += color;
+            sum.features.add(&features);
+        }
+        sum
+    }
+
+    // adds n samples to each pixel i, every pixel carries on from the samples it already has
+    fn add_samples(&mut self, work: &[(usize, u32)]) {
+        let sums: Vec<PixelSum> = work.par_iter()
+            .map(|&(i, n)| self.pixel_color(i, self.pixel_samples[i], n))
+            .collect();
+
+        for (&(i, n), sum) in work.iter().zip(sums) {
+            self.accum[i] += sum.color;
+            self.accum_squared[i] += sum.luminance_squared;
+            self.features[i].add(&sum.features);
+            self.pixel_samples[i] += n;
+        }
+    }
+
+    // the samples of the next round, split up by tile. adaptive sampling goes in rounds over the
+    // whole image, each one decided on from the image as the round before left it, so the order
+    // the tiles come in never changes which samples a pixel gets. false once there's nothing left
+    fn plan_round(&mut self) -> bool {
+        let first = self.rounds == 0;
+        self.rounds += 1;
+        let pixels = self.accum.len();
+        let work: Vec<(usize, u32)> = match self.adaptive {
+            None if first => (0..pixels).map(|i| (i, self.samples_per_pixel)).collect(),
+            None => Vec::new(),
+            Some(adaptive) if first => {
+                // spends samples_per_pixel samples per pixel on average, or the minimum if that's more
+                let min_samples = adaptive.min_samples_per_pixel;
+                self.budget = (self.samples_per_pixel.saturating_sub(min_samples) as u64) * pixels as u64;
+                (0..pixels).map(|i| (i, min_samples)).collect()
+            }
+            Some(adaptive) => self.adaptive_round(&adaptive),
+        };
+        if work.is_empty() {
+            return false;
+        }
+
+        let mut tile_of = vec![0; pixels];
+        for (t, tile) in self.tiles.iter().enumerate() {
+            for row in tile.row_start..tile.row_end {
+                for col in tile.col_start..tile.col_end {
+                    tile_of[(row * self.width + col) as usize] = t;
+                }
+            }
+        }
+        self.round = vec![Vec::new(); self.tiles.len()];
+        for (i, n) in work {
+            self.round[tile_of[i]].push((i, n));
+        }
+        true
+    }
+
+    // more samples for the pixels that are still too noisy, as long as the budget lasts
+    fn adaptive_round(&mut self, adaptive: &Adaptive) -> Vec<(usize, u32)> {
+        if self.budget == 0 {
+            return Vec::new();
+        }
+        let mut noisy: Vec<usize> = (0..self.accum.len()).filter(|&i| self.wants_more(adaptive, i)).collect();
+        if noisy.is_empty() {
+            return Vec::new();
+        }
+        // small rounds so the estimates get a say again before too much goes to one pixel
+        let min_samples = adaptive.min_samples_per_pixel;
+        let mut batch = (self.budget / noisy.len() as u64).min(min_samples as u64) as u32;
+        if batch == 0 {
+            // not enough left to go around, the noisiest pixels get the rest
+            let mut errors: Vec<(usize, f64)> = noisy.iter().map(|&i| (i, self.error(i))).collect();
+            errors.sort_by(|a, b| b.1.total_cmp(&a.1));
+            noisy = errors.into_iter().take(self.budget as usize).map(|(i, _)| i).collect();
+            batch = 1;
+        }
+        let work: Vec<(usize, u32)> = noisy.iter()
+            .map(|&i| (i, batch.min(adaptive.max_samples_per_pixel - self.pixel_samples[i])))
+            .collect();
+        self.budget -= work.iter().map(|&(_, n)| n as u64).sum::<u64>();
+        work
+    }
+
+    // pixels keep going while they or one of their neighbours are too noisy. a pixel whose first
+    // samples all happened to miss the light looks perfectly smooth on its own
+    fn wants_more(&self, adaptive: &Adaptive, i: usize) -> bool {
+        let samples = self.pixel_samples[i];
+        if samples < adaptive.min_samples_per_pixel {
+            return true;
+        }
+        if samples >= adaptive.max_samples_per_pixel {
+            return false;
+        }
+        let (width, height) = (self.width as usize, self.height as usize);
+        let (x, y) = (i % width, i / width);
+        for qy in y.saturating_sub(1)..(y + 2).min(height) {
+            for qx in x.saturating_sub(1)..(x + 2).min(width) {
+                if self.error(qy * width + qx) > adaptive.threshold {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn error(&self, i: usize) -> f64 {
+        adaptive::error(&self.accum[i], self.accum_squared[i], self.pixel_samples[i])
+    }
+
+    fn clear_pixel(&mut self, i: usize) {
+        self.accum[i] = Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 };
+        self.accum_squared[i] = 0.0;
+        self.features[i] = Features::zero();
+        self.pixel_samples[i] = 0;
+    }
+
+    // 1 for pixels without samples, their sums are all 0 anyway
+    fn samples_of(&self, i: usize) -> f64 {
+        self.pixel_samples[i].max(1) as f64
+    }
+
+    fn update_pixel(&mut self, i: usize) {
+        let mut color = self.accum[i].clone();
+        color /= self.samples_of(i);
+        self.buf[4 * i .. 4 * i + 4].copy_from_slice(&self.display.bytes(&color));
+    }
+
+    fn update_buf(&mut self) {
+        if self.view != Aov::Beauty {
+            let depth_scale = aov::depth_scale(&self.features);
+            let max_samples = self.pixel_samples.iter().copied().max().unwrap_or(0);
+            for (i, features) in self.features.iter().enumerate() {
+                let pixel = aov::pixel(self.view, features, self.pixel_samples[i], depth_scale, max_samples);
+                self.buf[4 * i .. 4 * i + 4].copy_from_slice(&pixel);
+            }
+            return;
+        }
+        if !self.denoise {
+            for i in 0..self.accum.len() {
+                self.update_pixel(i);
+            }
+            return;
+        }
+
+        let colors: Vec<Color> = (0..self.accum.len()).map(|i| self.accum[i].clone() * (1.0 / self.samples_of(i))).collect();
+        let squared: Vec<f64> = (0..self.accum.len()).map(|i| self.accum_squared[i] / self.samples_of(i)).collect();
+        let features: Vec<Features> = (0..self.accum.len()).map(|i| self.features[i].scaled(1.0 / self.samples_of(i))).collect();
+        let denoised = denoise::denoise(self.width as usize, self.height as usize, &self.pixel_samples, &colors, &squared, &features);
+        for (i, color) in denoised.iter().enumerate() {
+            self.buf[4 * i .. 4 * i + 4].copy_from_slice(&self.display.bytes(color));
+        }
+    }
+}
+
+impl Renderer {
+    // the default scene
+    pub fn new() -> Renderer {
+        let scene = scene::parse(scene::DEFAULT_SCENE, &HashMap::new(), &HashMap::new()).expect("the default scene doesn't parse");
+        Renderer::from_scene(scene)
+    }
+
+    // rgba, 4 bytes per pixel row by row from the top
+    pub fn buf(&self) -> &[u8] {
+        &self.buf
     }
 
     pub fn width(&self) -> u32 {
@@ -474,65 +1336,426 @@ impl Canvas {
         self.height
     }
 
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    // renders the whole image in one go, this blocks until it's done. it gets the same samples
+    // as render_for, only all of a round at once instead of tile by tile
     pub fn draw(&mut self) {
-        let mut ht: Vec<&Hitable> = Vec::new(); // works
-        ht.push(&Sphere {
-            center: Vec3 { x: 0.0, y: 0.0, z: -1.0 },
-            radius: 0.5,
-            material: &Lambertian { albedo: Color { r: 0.8, g: 0.3, b: 0.3, a: 1.0 } },
-        });
-        ht.push(&Sphere {
-            center: Vec3 { x: 0.0, y: -100.5, z: -1.0 },
-            radius: 100.0,
-            material: &Lambertian { albedo: Color { r: 0.8, g: 0.8, b: 0.0, a: 1.0 } },
-        });
-        ht.push(&Sphere {
-            center: Vec3 { x: 1.0, y: 0.0, z: -1.0 },
-            radius: 0.5,
-            material: &Metal { albedo: Color { r: 0.8, g: 0.6, b: 0.2, a: 1.0 }, fuzz: 1.0 },
-        });
-        ht.push(&Sphere {
-            center: Vec3 { x: -1.0, y: 0.0, z: -1.0 },
-            radius: 0.5,
-            material: &Metal { albedo: Color { r: 0.8, g: 0.8, b: 0.8, a: 1.0 }, fuzz: 0.3 },
-        });
-        let world = HitableList { 
-            hitables: ht,
-        };
+        self.reset();
+        while self.plan_round() {
+            let work: Vec<(usize, u32)> = self.round.drain(..).flatten().collect();
+            self.add_samples(&work);
+        }
+        self.round = vec![Vec::new(); self.tiles.len()];
+        self.next_tile = self.tiles.len();
+        self.samples = self.samples_per_pixel;
+        self.update_buf();
+    }
 
-        let camera = Camera::default();
-        let ns = 100;
-        for row in 0..self.height {
-            for col in 0..self.width {
-                let i = (row * self.width + col) as usize;
-                // some sampling for antialiasing
-                let mut color_sum = Color{ r: 0.0, g: 0.0, b: 0.0, a: 0.0 };
-                for _s in 0..ns {
-                    let u_offset = rand::thread_rng().gen::<f64>();
-                    let v_offset = rand::thread_rng().gen::<f64>();
-                    let u_right: f64 = (col as f64 + u_offset) / (self.width as f64);
-                    let v_up: f64 = ((self.height - row) as f64 + v_offset) / (self.height as f64);
-                    let ray = camera.calculate_ray(u_right, v_up);
-
-                    let color = ray.get_color(&world, 0);
-                    color_sum += color;
+    // renders whole tiles until ms milliseconds are used up and returns whether the image is done.
+    // the next call continues with the next tile, at least one tile gets done per call. with
+    // adaptive sampling the tiles come around once per round
+    pub fn render_for(&mut self, ms: f64) -> bool {
+        let deadline = now_ms() + ms;
+        if self.next_tile == 0 && self.rounds == 0 {
+            self.reset();
+            self.plan_round();
+        }
 
+        while self.next_tile < self.round.len() {
+            let work = std::mem::take(&mut self.round[self.next_tile]);
+            self.add_samples(&work);
+            if self.view == Aov::Beauty {
+                for &(i, _) in work.iter() {
+                    self.update_pixel(i);
                 }
-                color_sum /= ns as f64;
-                color_sum.r = color_sum.r.sqrt();
-                color_sum.g = color_sum.g.sqrt();
-                color_sum.b = color_sum.b.sqrt();
-                color_sum.a = 1.0;
-                self.buf[4 * i .. 4 * i + 4].copy_from_slice(&color_sum.bytes());
+            }
+            self.next_tile += 1;
+            if self.next_tile == self.round.len() && self.plan_round() {
+                self.next_tile = 0;
+            }
+            if now_ms() >= deadline {
+                break;
             }
         }
+
+        let done = self.next_tile == self.round.len();
+        if done {
+            self.samples = self.samples_per_pixel;
+        }
+        // the tiles show up unfiltered while they come in, the denoiser needs all of them. the
+        // depth aov looks at all of them for its scale too, so the others just keep up
+        if (done && self.denoise) || self.view != Aov::Beauty {
+            self.update_buf();
+        }
+        done
+    }
+
+    pub fn set_tile_order(&mut self, order: TileOrder) {
+        self.tile_order = order;
+        self.tiles = make_tiles(self.width, self.height, order);
+        self.reset();
+    }
+
+    // the camera keeps its vertical field of view, so a wider picture shows more on the sides
+    pub fn set_size(&mut self, width: u32, height: u32) -> Result<(), String> {
+        if width == 0 || height == 0 {
+            return Err("width and height have to be at least 1".to_string());
+        }
+        self.width = width;
+        self.height = height;
+        self.buf = vec![0; (width * height) as usize * 4];
+        self.accum = vec![Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 }; (width * height) as usize];
+        self.accum_squared = vec![0.0; (width * height) as usize];
+        self.features = vec![Features::zero(); (width * height) as usize];
+        self.pixel_samples = vec![0; (width * height) as usize];
+        self.tiles = make_tiles(width, height, self.tile_order);
+        self.camera.set_aspect(width as f64 / height as f64);
+        self.reset();
+        Ok(())
+    }
+
+    // used by draw and render_for, with adaptive sampling it's the average
+    pub fn set_samples_per_pixel(&mut self, samples_per_pixel: u32) {
+        self.samples_per_pixel = samples_per_pixel;
+        self.reset();
+    }
+
+    // pixels get between min and max samples, more of them the noisier they are. a pixel is done
+    // once it and its neighbours are less noisy than threshold (0.02 is hard to tell from a lot
+    // more samples), so draw and render_for can use fewer samples than samples_per_pixel
+    pub fn set_adaptive_sampling(&mut self, min_samples_per_pixel: u32, max_samples_per_pixel: u32,
+                                 threshold: f64) -> Result<(), String> {
+        let adaptive = Adaptive { min_samples_per_pixel, max_samples_per_pixel, threshold };
+        adaptive.check()?;
+        self.adaptive = Some(adaptive);
+        self.reset();
+        Ok(())
+    }
+
+    // every pixel gets samples_per_pixel samples again
+    pub fn set_uniform_sampling(&mut self) {
+        self.adaptive = None;
+        self.reset();
+    }
+
+    // paths end after this many bounces even if russian roulette hasn't stopped them yet. 0 only
+    // shows what glows by itself
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.max_depth = max_depth;
+        self.reset();
+    }
+
+    // adds n more samples to every pixel and shows the average so far. with adaptive sampling
+    // only the pixels that are still too noisy get them
+    pub fn draw_samples(&mut self, n: u32) {
+        let work: Vec<(usize, u32)> = (0..self.accum.len())
+            .filter_map(|i| match self.adaptive {
+                None => Some((i, n)),
+                Some(adaptive) if self.wants_more(&adaptive, i) => {
+                    Some((i, n.min(adaptive.max_samples_per_pixel - self.pixel_samples[i])))
+                }
+                Some(_) => None,
+            })
+            .collect();
+        self.add_samples(&work);
+        self.samples += n;
+        self.update_buf();
+    }
+
+    // the display settings below only change how the samples so far are shown, they don't
+    // throw anything away
+    pub fn set_tone_map(&mut self, tone_map: ToneMap) {
+        self.display.tone_map = tone_map;
+        self.update_buf();
+    }
+
+    // in stops, 1 is twice as bright and -1 half as bright
+    pub fn set_exposure(&mut self, exposure: f64) {
+        self.display.exposure = exposure;
+        self.update_buf();
+    }
+
+    pub fn set_srgb_output(&mut self) {
+        self.display.transfer = Transfer::Srgb;
+        self.update_buf();
+    }
+
+    // encodes with a plain power curve instead of srgb, 2.2 comes close to it
+    pub fn set_output_gamma(&mut self, gamma: f64) -> Result<(), String> {
+        if gamma.is_nan() || gamma <= 0.0 {
+            return Err("the gamma has to be above 0".to_string());
+        }
+        self.display.transfer = Transfer::Gamma(gamma);
+        self.update_buf();
+        Ok(())
+    }
+
+    // filters the noise out of what buf shows, guided by what the camera rays hit first. the
+    // samples themselves stay as they are so turning it off again brings the noise back
+    pub fn set_denoise(&mut self, denoise: bool) {
+        self.denoise = denoise;
+        self.update_buf();
+    }
+
+    // shows one of the aovs in buf instead of the picture, Aov::Beauty goes back to it
+    pub fn set_view(&mut self, view: Aov) {
+        self.view = view;
+        self.update_buf();
+    }
+
+    // the aovs as numbers, averaged over the samples so far. they get copied out of the renderer
+    // when asked for, so they don't change until they're asked for again
+
+    // distance from the camera along the ray for each pixel, 0 where nothing got hit
+    pub fn depth(&mut self) -> &[f32] {
+        self.aov_buffers.depth = self.features.iter().map(|features| aov::depth(features) as f32).collect();
+        &self.aov_buffers.depth
+    }
+
+    // x y z for each pixel in world space, 0 0 0 where nothing got hit
+    pub fn normals(&mut self) -> &[f32] {
+        self.aov_buffers.normal = self.features.iter().flat_map(aov::normal).collect();
+        &self.aov_buffers.normal
+    }
+
+    // linear r g b for each pixel, the background where nothing got hit
+    pub fn albedo(&mut self) -> &[f32] {
+        self.aov_buffers.albedo = (0..self.features.len())
+            .flat_map(|i| {
+                let albedo = &self.features[i].albedo;
+                let scale = 1.0 / self.samples_of(i);
+                [albedo.r, albedo.g, albedo.b].map(|value| (scale * value) as f32)
+            })
+            .collect();
+        &self.aov_buffers.albedo
+    }
+
+    // the materials are numbered from 1 in alphabetical order of their names. 0 for nothing and
+    // for materials from obj files
+    pub fn material_ids(&mut self) -> &[u32] {
+        self.aov_buffers.material_id = self.features.iter().map(|features| features.material_id).collect();
+        &self.aov_buffers.material_id
+    }
+
+    // the objects are numbered from 1 in the order of the scene, obj meshes come after them in
+    // the order they were added. 0 for nothing
+    pub fn object_ids(&mut self) -> &[u32] {
+        self.aov_buffers.object_id = self.features.iter().map(|features| features.object_id).collect();
+        &self.aov_buffers.object_id
+    }
+
+    // how many samples each pixel got, they only differ with adaptive sampling
+    pub fn sample_counts(&self) -> &[u32] {
+        &self.pixel_samples
+    }
+
+    // the average of the samples so far as linear r g b for each pixel, before exposure, tone
+    // mapping and the denoiser
+    fn linear(&self) -> Vec<f32> {
+        (0..self.accum.len())
+            .flat_map(|i| {
+                let color = self.accum[i].clone() * (1.0 / self.samples_of(i));
+                [color.r as f32, color.g as f32, color.b as f32]
+            })
+            .collect()
+    }
+
+    // the picture as a pfm file, linear with the full range of the samples
+    pub fn pfm(&self) -> Vec<u8> {
+        export::pfm(self.width, self.height, &self.linear())
+    }
+
+    // the picture as an openexr file with the aovs as extra channels: R G B, Z for the depth,
+    // normal.X/Y/Z, albedo.R/G/B, material_id, object_id and sample_count
+    pub fn exr(&mut self) -> Vec<u8> {
+        let linear = self.linear();
+        let normals: Vec<f32> = self.normals().to_vec();
+        let albedo: Vec<f32> = self.albedo().to_vec();
+        let channel = |name: &str, values: export::Values| export::Channel { name: name.to_string(), values };
+        // every third value starting at offset
+        let every_third = |values: &[f32], offset: usize| values.iter().skip(offset).step_by(3).copied().collect();
+
+        let channels = vec![
+            channel("R", export::Values::Float(every_third(&linear, 0))),
+            channel("G", export::Values::Float(every_third(&linear, 1))),
+            channel("B", export::Values::Float(every_third(&linear, 2))),
+            channel("Z", export::Values::Float(self.depth().to_vec())),
+            channel("normal.X", export::Values::Float(every_third(&normals, 0))),
+            channel("normal.Y", export::Values::Float(every_third(&normals, 1))),
+            channel("normal.Z", export::Values::Float(every_third(&normals, 2))),
+            channel("albedo.R", export::Values::Float(every_third(&albedo, 0))),
+            channel("albedo.G", export::Values::Float(every_third(&albedo, 1))),
+            channel("albedo.B", export::Values::Float(every_third(&albedo, 2))),
+            channel("material_id", export::Values::Uint(self.material_ids().to_vec())),
+            channel("object_id", export::Values::Uint(self.object_ids().to_vec())),
+            channel("sample_count", export::Values::Uint(self.pixel_samples.clone())),
+        ];
+        export::exr(self.width, self.height, channels)
+    }
+
+    // the same seed gives the same image, however many threads render it
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.reset();
+    }
+
+    // how the samples get spread out, the stratified one works best when the samples per pixel
+    // are set to what will actually get rendered
+    pub fn set_sampler(&mut self, sampler: SamplerKind) {
+        self.sampler = sampler;
+        self.reset();
+    }
+
+    // throws away the accumulated samples, needed whenever the picture changes
+    pub fn reset(&mut self) {
+        for i in 0..self.accum.len() {
+            self.clear_pixel(i);
+        }
+        self.samples = 0;
+        self.next_tile = 0;
+        self.round.clear();
+        self.rounds = 0;
+        self.budget = 0;
+    }
+
+    pub fn set_camera(&mut self, look_from: [f64; 3], look_at: [f64; 3], vup: [f64; 3], vfov: f64) -> Result<(), String> {
+        let vec3 = |[x, y, z]: [f64; 3]| Vec3 { x, y, z };
+        let mut camera = Camera::new(
+            vec3(look_from),
+            &vec3(look_at),
+            &vec3(vup),
+            vfov,
+            self.width as f64 / self.height as f64,
+        )?;
+        // keep the lens settings when moving the camera around
+        camera.set_lens(2.0 * self.camera.lens_radius, self.camera.focus_dist);
+        self.camera = camera;
+        self.reset();
+        Ok(())
+    }
+
+    // aperture 0 turns depth of field off again
+    pub fn set_lens(&mut self, aperture: f64, focus_dist: f64) -> Result<(), String> {
+        Camera::check_lens(aperture, focus_dist)?;
+        self.camera.set_lens(aperture, focus_dist);
+        self.reset();
+        Ok(())
+    }
+
+    // replaces everything including the size, so width, height and buf have to be asked for again.
+    // only the added images stay
+    pub fn load_scene(&mut self, json: &str) -> Result<(), String> {
+        let scene = scene::parse(json, &self.images, &self.meshes)?;
+        let images = std::mem::take(&mut self.images);
+        let meshes = std::mem::take(&mut self.meshes);
+        *self = Renderer::from_scene(scene);
+        self.images = images;
+        self.meshes = meshes;
+        Ok(())
+    }
+
+    // black turns the sky off for scenes lit only by lights
+    pub fn set_background(&mut self, r: f64, g: f64, b: f64) {
+        self.background = Background::Solid(Color { r, g, b, a: 1.0 });
+        self.reset();
+    }
+
+    pub fn set_sky_background(&mut self) {
+        self.background = Background::Sky;
+        self.reset();
+    }
+
+    // lights the scene with one of the added images, wrapped around it equirectangularly.
+    // rotation is in degrees around the up axis. importance sampling aims more rays at the
+    // bright parts, which helps a lot with a small sun in the picture
+    pub fn set_environment(&mut self, image: &str, rotation: f64, importance_sampling: bool) -> Result<(), String> {
+        let image = self.images.get(image)
+            .cloned()
+            .ok_or_else(|| format!("no image called \"{}\" was added", image))?;
+        self.background = Background::Environment(EnvironmentMap::new(image, rotation, importance_sampling));
+        self.reset();
+        Ok(())
+    }
+
+    // png, jpeg or radiance hdr bytes, image textures and environment maps in scenes loaded
+    // afterwards can use it by its name
+    pub fn add_image(&mut self, name: &str, bytes: &[u8]) -> Result<(), String> {
+        let image = texture::Image::decode(bytes)?;
+        self.images.insert(name.to_string(), Arc::new(image));
+        Ok(())
+    }
+
+    // a wavefront obj mesh that scenes can place by its name with { "mesh": { "name": ... } },
+    // as often as they like through definitions and instances. like images it has to be there
+    // before the scene gets loaded
+    pub fn add_mesh(&mut self, name: &str, obj: &[u8], mtl: Option<&[u8]>) -> Result<(), String> {
+        let mesh = parse_obj(obj, mtl)?;
+        self.meshes.insert(name.to_string(), mesh);
+        Ok(())
+    }
+
+    // adds a wavefront obj mesh to the scene, mtl is the material library it uses if there is one.
+    // faces without a material come out grey
+    pub fn add_obj(&mut self, obj: &[u8], mtl: Option<&[u8]>) -> Result<(), String> {
+        let mesh = parse_obj(obj, mtl)?;
+        self.objects += 1;
+        self.world.hitables.push(Box::new(NumberedObject { id: self.objects, hitable: Box::new(mesh) }));
+        self.reset();
+        Ok(())
+    }
+}
+
+fn parse_obj(obj: &[u8], mtl: Option<&[u8]>) -> Result<mesh::Mesh, String> {
+    let obj = std::str::from_utf8(obj).map_err(|err| err.to_string())?;
+    let mtl = match mtl {
+        Some(mtl) => Some(std::str::from_utf8(mtl).map_err(|err| err.to_string())?),
+        None => None,
+    };
+    let default_material = Arc::new(Lambertian {
+        albedo: Arc::new(SolidColor { color: Color { r: 0.5, g: 0.5, b: 0.5, a: 1.0 } }),
+    });
+    mesh::Mesh::from_obj(obj, mtl, default_material)
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every tile order and draw have to come out the same, the seed is all that decides the picture
+    fn check_same_picture(adaptive: bool) {
+        let mut renderer = Renderer::new();
+        renderer.set_size(40, 24).unwrap();
+        renderer.set_samples_per_pixel(16);
+        renderer.set_seed(7);
+        if adaptive {
+            renderer.set_adaptive_sampling(4, 64, 0.05).unwrap();
+        }
+        renderer.draw();
+        let drawn = renderer.buf().to_vec();
+
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            renderer.set_tile_order(order);
+            while !renderer.render_for(0.0) {}
+            assert!(renderer.buf() == &drawn[..], "render_for with a different tile order than draw");
+        }
+    }
+
+    #[test]
+    fn tile_order_doesnt_change_the_picture() {
+        check_same_picture(false);
     }
 
-    pub fn new() -> Canvas {
-        console_error_panic_hook::set_once();
-        let width = 200u32;
-        let height = 100u32;
-        let buf = vec![0; (width * height) as usize * 4];
-        Canvas { width, height, buf }
+    #[test]
+    fn tile_order_doesnt_change_the_adaptive_picture() {
+        check_same_picture(true);
     }
 }
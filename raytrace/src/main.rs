@@ -0,0 +1,257 @@
+// renders a scene file without a browser, e.g.
+//   raytrace scenes/cornell.json --width 400 --height 400 --samples 500 -o cornell.png
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+
+use banana::{Aov, Renderer, SamplerKind};
+
+const USAGE: &str = "usage: raytrace [scene.json] [options]
+
+renders the scene, or the default one if there is none, and saves the picture.
+the size, samples, seed, sampler and max depth come from the scene's settings unless they're
+given here
+
+options:
+  -o, --output <file>        .png or .ppm, out.png if there is none. .pfm and .exr get the
+                             linear picture, the exr with the aovs as extra channels
+      --width <pixels>
+      --height <pixels>
+      --samples <n>          samples per pixel
+      --seed <n>
+      --sampler <name>       independent, stratified, halton or sobol
+      --max-depth <n>        bounces per path at most
+      --adaptive             more samples for noisy pixels, --samples is the average then
+      --min-samples <n>      per pixel with adaptive sampling, 16 if not given
+      --max-samples <n>      per pixel with adaptive sampling, 1024 if not given
+      --threshold <error>    how little noise adaptive sampling stops at, 0.02 if not given
+      --denoise              filters the noise out of the picture
+      --aov <name>           saves depth, normal, albedo, material_id, object_id or sample_count instead
+      --image <name>=<file>  an image the scene can use by its name, works more than once
+      --obj <name>=<file>[,<mtl file>]
+                             a mesh the scene can use by its name, works more than once
+      --help";
+
+struct Options {
+    scene: Option<String>,
+    output: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    samples: Option<u32>,
+    seed: Option<u64>,
+    sampler: Option<SamplerKind>,
+    max_depth: Option<u32>,
+    // any of these turns adaptive sampling on
+    adaptive: bool,
+    min_samples: Option<u32>,
+    max_samples: Option<u32>,
+    threshold: Option<f64>,
+    denoise: bool,
+    aov: Aov,
+    images: Vec<(String, String)>,
+    // name, obj file and maybe an mtl file
+    meshes: Vec<(String, String, Option<String>)>,
+}
+
+enum Format {
+    Png,
+    Ppm,
+    Pfm,
+    Exr,
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("raytrace: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(&options) {
+        eprintln!("raytrace: {}", err);
+        process::exit(1);
+    }
+}
+
+// None if the usage should be shown
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        scene: None,
+        output: "out.png".to_string(),
+        width: None,
+        height: None,
+        samples: None,
+        seed: None,
+        sampler: None,
+        max_depth: None,
+        adaptive: false,
+        min_samples: None,
+        max_samples: None,
+        threshold: None,
+        denoise: false,
+        aov: Aov::Beauty,
+        images: Vec::new(),
+        meshes: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--help" => return Ok(None),
+            "-o" | "--output" => options.output = value()?,
+            "--width" => options.width = Some(parse_number(&arg, &value()?)?),
+            "--height" => options.height = Some(parse_number(&arg, &value()?)?),
+            "--samples" => options.samples = Some(parse_number(&arg, &value()?)?),
+            "--seed" => options.seed = Some(parse_number(&arg, &value()?)?),
+            "--sampler" => options.sampler = Some(parse_sampler(&value()?)?),
+            "--max-depth" => options.max_depth = Some(parse_number(&arg, &value()?)?),
+            "--adaptive" => options.adaptive = true,
+            "--min-samples" => options.min_samples = Some(parse_number(&arg, &value()?)?),
+            "--max-samples" => options.max_samples = Some(parse_number(&arg, &value()?)?),
+            "--threshold" => options.threshold = Some(parse_number(&arg, &value()?)?),
+            "--denoise" => options.denoise = true,
+            "--aov" => options.aov = parse_aov(&value()?)?,
+            "--image" => {
+                let value = value()?;
+                let (name, path) = value.split_once('=')
+                    .ok_or_else(|| format!("--image wants name=file, not \"{}\"", value))?;
+                options.images.push((name.to_string(), path.to_string()));
+            }
+            "--obj" => {
+                let value = value()?;
+                let (name, paths) = value.split_once('=')
+                    .ok_or_else(|| format!("--obj wants name=file or name=file,mtl, not \"{}\"", value))?;
+                let (obj, mtl) = match paths.split_once(',') {
+                    Some((obj, mtl)) => (obj, Some(mtl.to_string())),
+                    None => (paths, None),
+                };
+                options.meshes.push((name.to_string(), obj.to_string(), mtl));
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if options.scene.is_none() => options.scene = Some(arg),
+            _ => return Err(format!("only one scene at a time, \"{}\" is one too many", arg)),
+        }
+    }
+
+    Ok(Some(options))
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} wants a number, not \"{}\"", option, value))
+}
+
+fn parse_sampler(name: &str) -> Result<SamplerKind, String> {
+    match name {
+        "independent" => Ok(SamplerKind::Independent),
+        "stratified" => Ok(SamplerKind::Stratified),
+        "halton" => Ok(SamplerKind::Halton),
+        "sobol" => Ok(SamplerKind::Sobol),
+        _ => Err(format!("--sampler wants independent, stratified, halton or sobol, not \"{}\"", name)),
+    }
+}
+
+fn parse_aov(name: &str) -> Result<Aov, String> {
+    match name {
+        "beauty" => Ok(Aov::Beauty),
+        "depth" => Ok(Aov::Depth),
+        "normal" => Ok(Aov::Normal),
+        "albedo" => Ok(Aov::Albedo),
+        "material_id" => Ok(Aov::MaterialId),
+        "object_id" => Ok(Aov::ObjectId),
+        "sample_count" => Ok(Aov::SampleCount),
+        _ => Err(format!("--aov wants depth, normal, albedo, material_id, object_id or sample_count, not \"{}\"", name)),
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    // checked first so a typo doesn't cost a whole render
+    let format = match Path::new(&options.output).extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("png") => Format::Png,
+        Some(extension) if extension.eq_ignore_ascii_case("ppm") => Format::Ppm,
+        Some(extension) if extension.eq_ignore_ascii_case("pfm") => Format::Pfm,
+        Some(extension) if extension.eq_ignore_ascii_case("exr") => Format::Exr,
+        _ => return Err(format!("{}: only .png, .ppm, .pfm and .exr can be written", options.output)),
+    };
+
+    let mut renderer = Renderer::new();
+    // the scene can only use images and meshes that are there before it gets loaded
+    for (name, path) in options.images.iter() {
+        let bytes = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+        renderer.add_image(name, &bytes).map_err(|err| format!("{}: {}", path, err))?;
+    }
+    for (name, path, mtl_path) in options.meshes.iter() {
+        let obj = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+        let mtl = match mtl_path {
+            Some(mtl_path) => Some(fs::read(mtl_path).map_err(|err| format!("{}: {}", mtl_path, err))?),
+            None => None,
+        };
+        renderer.add_mesh(name, &obj, mtl.as_deref()).map_err(|err| format!("{}: {}", path, err))?;
+    }
+    if let Some(scene) = &options.scene {
+        let json = fs::read_to_string(scene).map_err(|err| format!("{}: {}", scene, err))?;
+        renderer.load_scene(&json).map_err(|err| format!("{}: {}", scene, err))?;
+    }
+
+    if options.width.is_some() || options.height.is_some() {
+        let width = options.width.unwrap_or_else(|| renderer.width());
+        let height = options.height.unwrap_or_else(|| renderer.height());
+        renderer.set_size(width, height)?;
+    }
+    if let Some(samples) = options.samples {
+        renderer.set_samples_per_pixel(samples);
+    }
+    if let Some(seed) = options.seed {
+        renderer.set_seed(seed);
+    }
+    if let Some(sampler) = options.sampler {
+        renderer.set_sampler(sampler);
+    }
+    if let Some(max_depth) = options.max_depth {
+        renderer.set_max_depth(max_depth);
+    }
+    if options.adaptive || options.min_samples.is_some() || options.max_samples.is_some() || options.threshold.is_some() {
+        renderer.set_adaptive_sampling(
+            options.min_samples.unwrap_or(16),
+            options.max_samples.unwrap_or(1024),
+            options.threshold.unwrap_or(0.02),
+        )?;
+    }
+    if options.denoise {
+        renderer.set_denoise(true);
+    }
+    renderer.set_view(options.aov);
+
+    let start = Instant::now();
+    renderer.draw();
+    // adaptive sampling can stop short of the samples asked for
+    let counts = renderer.sample_counts();
+    let average = counts.iter().map(|&n| n as f64).sum::<f64>() / counts.len() as f64;
+    eprintln!(
+        "{}x{} with {:.1} samples per pixel in {:.2}s",
+        renderer.width(),
+        renderer.height(),
+        average,
+        start.elapsed().as_secs_f64(),
+    );
+
+    let (width, height) = (renderer.width(), renderer.height());
+    let rgb: Vec<u8> = renderer.buf().chunks(4).flat_map(|pixel| &pixel[..3]).copied().collect();
+    match format {
+        Format::Png => image::save_buffer(&options.output, &rgb, width, height, image::ColorType::Rgb8)
+            .map_err(|err| format!("{}: {}", options.output, err)),
+        Format::Ppm => {
+            let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+            ppm.extend_from_slice(&rgb);
+            fs::write(&options.output, ppm).map_err(|err| format!("{}: {}", options.output, err))
+        }
+        Format::Pfm => fs::write(&options.output, renderer.pfm()).map_err(|err| format!("{}: {}", options.output, err)),
+        Format::Exr => fs::write(&options.output, renderer.exr()).map_err(|err| format!("{}: {}", options.output, err)),
+    }
+}
@@ -0,0 +1,390 @@
+// triangles and wavefront obj meshes
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::bvh::{Aabb, BvhNode};
+use crate::texture::SolidColor;
+use crate::{cross, dot, Color, Dielectric, HitRecord, Hitable, Lambertian, Material, Metal, Ray, Vec3};
+
+pub(crate) struct Triangle {
+    pub(crate) vertices: [Vec3; 3],
+    // without vertex normals the triangle is flat shaded
+    pub(crate) normals: Option<[Vec3; 3]>,
+    // without texture coordinates u and v are the barycentric coordinates
+    pub(crate) uvs: Option<[(f64, f64); 3]>,
+    pub(crate) material: Arc<dyn Material>,
+}
+
+impl Hitable for Triangle {
+    // möller-trumbore, solves for t and the barycentric coordinates in one go
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let [v0, v1, v2] = &self.vertices;
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+        let pvec = cross(&ray.direction, &edge2);
+        let det = dot(&edge1, &pvec);
+        // the ray is parallel to the triangle
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = &ray.start - v0;
+        let b1 = dot(&tvec, &pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+
+        let qvec = cross(&tvec, &edge1);
+        let b2 = dot(&ray.direction, &qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+
+        let time = dot(&edge2, &qvec) * inv_det;
+        if time <= t_min || time >= t_max {
+            return None;
+        }
+
+        let b0 = 1.0 - b1 - b2;
+        let normal = match &self.normals {
+            Some([n0, n1, n2]) => (b0 * n0 + b1 * n1 + b2 * n2).normalize(),
+            None => cross(&edge1, &edge2).normalize(),
+        };
+        let (u, v) = match &self.uvs {
+            Some([uv0, uv1, uv2]) => (
+                b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+                b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+            ),
+            None => (b1, b2),
+        };
+
+        Some(HitRecord {
+            time,
+            point: ray.eval(time),
+            normal,
+            u,
+            v,
+            material: &*self.material,
+            object_id: 0,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let [v0, v1, v2] = &self.vertices;
+        // padded a bit, a triangle lying in an axis plane would give a box without thickness
+        let pad = Vec3 { x: 1e-4, y: 1e-4, z: 1e-4 };
+        let bbox = Aabb { min: v0.clone(), max: v0.clone() }
+            .union(&Aabb { min: v1.clone(), max: v1.clone() })
+            .union(&Aabb { min: v2.clone(), max: v2.clone() });
+        Some(Aabb {
+            min: &bbox.min - &pad,
+            max: &bbox.max + &pad,
+        })
+    }
+}
+
+// a bunch of triangles with their own bvh so the mesh as a whole can go into a scene. clones
+// share the triangles, so a mesh can be placed as often as you like
+#[derive(Clone)]
+pub(crate) struct Mesh {
+    root: Arc<BvhNode>,
+}
+
+impl Mesh {
+    // faces without a material from the mtl file get default_material. mtl is the text of
+    // the material library, the mtllib line itself is ignored because there's no file system
+    pub(crate) fn from_obj(obj: &str, mtl: Option<&str>, default_material: Arc<dyn Material>) -> Result<Mesh, String> {
+        let materials = match mtl {
+            Some(mtl) => parse_mtl(mtl)?,
+            None => HashMap::new(),
+        };
+        let triangles = parse_faces(obj, &materials, default_material)?.into_iter()
+            .map(|triangle| Box::new(triangle) as Box<dyn Hitable>)
+            .collect();
+        let root = BvhNode::new(triangles).ok_or_else(|| "the obj file has no faces".to_string())?;
+        Ok(Mesh { root: Arc::new(root) })
+    }
+}
+
+// the triangles of all the faces, in the order they're in the file
+fn parse_faces(obj: &str, materials: &HashMap<String, Arc<dyn Material>>, default_material: Arc<dyn Material>) -> Result<Vec<Triangle>, String> {
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut texcoords: Vec<(f64, f64)> = Vec::new();
+    let mut material = default_material.clone();
+    let mut triangles: Vec<Triangle> = Vec::new();
+
+    for (line_number, line) in obj.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", line_number + 1, message);
+        let mut parts = line.split_whitespace();
+        let keyword = match parts.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+        let args: Vec<&str> = parts.collect();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&args).map_err(|err| error(&err))?),
+            "vn" => normals.push(parse_vec3(&args).map_err(|err| error(&err))?),
+            "vt" => {
+                let uv = parse_floats(&args).map_err(|err| error(&err))?;
+                match uv.as_slice() {
+                    [u] => texcoords.push((*u, 0.0)),
+                    [u, v, ..] => texcoords.push((*u, *v)),
+                    [] => return Err(error("vt needs at least one coordinate")),
+                }
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error("a face needs at least three vertices"));
+                }
+                let corners = args.iter()
+                    .map(|corner| parse_corner(corner, positions.len(), texcoords.len(), normals.len()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| error(&err))?;
+
+                // polygons get split into a fan around the first corner
+                for i in 1..corners.len() - 1 {
+                    let face = [&corners[0], &corners[i], &corners[i + 1]];
+                    triangles.push(Triangle {
+                        vertices: [
+                            positions[face[0].position].clone(),
+                            positions[face[1].position].clone(),
+                            positions[face[2].position].clone(),
+                        ],
+                        normals: match (face[0].normal, face[1].normal, face[2].normal) {
+                            (Some(n0), Some(n1), Some(n2)) => {
+                                Some([normals[n0].clone(), normals[n1].clone(), normals[n2].clone()])
+                            }
+                            _ => None,
+                        },
+                        uvs: match (face[0].texcoord, face[1].texcoord, face[2].texcoord) {
+                            (Some(t0), Some(t1), Some(t2)) => Some([texcoords[t0], texcoords[t1], texcoords[t2]]),
+                            _ => None,
+                        },
+                        material: material.clone(),
+                    });
+                }
+            }
+            "usemtl" => {
+                let name = args.first().ok_or_else(|| error("usemtl needs a name"))?;
+                material = match materials.get(*name) {
+                    Some(material) => material.clone(),
+                    None => default_material.clone(),
+                };
+            }
+            // groups, objects, smoothing groups, lines etc. don't matter for rendering
+            _ => {}
+        }
+    }
+    Ok(triangles)
+}
+
+impl Hitable for Mesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.root.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.root.bounding_box()
+    }
+}
+
+// zero based indices into the position, texcoord and normal lists
+struct Corner {
+    position: usize,
+    texcoord: Option<usize>,
+    normal: Option<usize>,
+}
+
+// v, v/vt, v//vn or v/vt/vn
+fn parse_corner(corner: &str, n_positions: usize, n_texcoords: usize, n_normals: usize) -> Result<Corner, String> {
+    let mut indices = corner.split('/');
+    let position = match indices.next() {
+        Some(index) => resolve_index(index, n_positions)?,
+        None => return Err(format!("bad face vertex \"{}\"", corner)),
+    };
+    let texcoord = match indices.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, n_texcoords)?),
+    };
+    let normal = match indices.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, n_normals)?),
+    };
+    Ok(Corner { position, texcoord, normal })
+}
+
+// obj counts from 1, negative indices count backwards from the last element read so far
+fn resolve_index(index: &str, len: usize) -> Result<usize, String> {
+    let index: i64 = index.parse().map_err(|_| format!("bad index \"{}\"", index))?;
+    let resolved = if index < 0 { len as i64 + index } else { index - 1 };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(format!("index {} is out of range", index));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_floats(args: &[&str]) -> Result<Vec<f64>, String> {
+    args.iter()
+        .map(|arg| arg.parse::<f64>().map_err(|_| format!("bad number \"{}\"", arg)))
+        .collect()
+}
+
+fn parse_vec3(args: &[&str]) -> Result<Vec3, String> {
+    match parse_floats(args)?.as_slice() {
+        [x, y, z, ..] => Ok(Vec3 { x: *x, y: *y, z: *z }),
+        _ => Err("expected three coordinates".to_string()),
+    }
+}
+
+// mtl only describes phong style materials so this picks whichever of ours is closest:
+// transparent ones become glass, ones with reflections turned on become metal
+fn parse_mtl(mtl: &str) -> Result<HashMap<String, Arc<dyn Material>>, String> {
+    struct MtlEntry {
+        diffuse: Vec3,
+        specular: Vec3,
+        shininess: f64,
+        ref_idx: f64,
+        dissolve: f64,
+        illum: u32,
+    }
+
+    fn build(entry: &MtlEntry) -> Arc<dyn Material> {
+        let color = |v: &Vec3| Arc::new(SolidColor { color: Color { r: v.x, g: v.y, b: v.z, a: 1.0 } });
+        if entry.dissolve < 1.0 || entry.illum == 4 || entry.illum == 6 || entry.illum == 7 {
+            Arc::new(Dielectric { ref_idx: entry.ref_idx })
+        } else if entry.illum == 3 || entry.illum == 5 {
+            // shininess goes up to 1000, the shinier the less fuzz
+            Arc::new(Metal { albedo: color(&entry.specular), fuzz: (1.0 - entry.shininess / 1000.0).clamp(0.0, 1.0) })
+        } else {
+            Arc::new(Lambertian { albedo: color(&entry.diffuse) })
+        }
+    }
+
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut current: Option<(String, MtlEntry)> = None;
+
+    for (line_number, line) in mtl.lines().enumerate() {
+        let error = |message: &str| format!("mtl line {}: {}", line_number + 1, message);
+        let mut parts = line.split_whitespace();
+        let keyword = match parts.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+        let args: Vec<&str> = parts.collect();
+
+        if keyword == "newmtl" {
+            if let Some((name, entry)) = current.take() {
+                materials.insert(name, build(&entry));
+            }
+            let name = args.first().ok_or_else(|| error("newmtl needs a name"))?;
+            current = Some((name.to_string(), MtlEntry {
+                diffuse: Vec3 { x: 0.8, y: 0.8, z: 0.8 },
+                specular: Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+                shininess: 0.0,
+                ref_idx: 1.5,
+                dissolve: 1.0,
+                illum: 2,
+            }));
+            continue;
+        }
+
+        let entry = match current.as_mut() {
+            Some((_, entry)) => entry,
+            None => continue,
+        };
+        let number = || -> Result<f64, String> {
+            let arg = args.first().ok_or_else(|| error("missing value"))?;
+            arg.parse().map_err(|_| error(&format!("bad number \"{}\"", arg)))
+        };
+        match keyword {
+            "Kd" => entry.diffuse = parse_vec3(&args).map_err(|err| error(&err))?,
+            "Ks" => entry.specular = parse_vec3(&args).map_err(|err| error(&err))?,
+            "Ns" => entry.shininess = number()?,
+            "Ni" => entry.ref_idx = number()?,
+            "d" => entry.dissolve = number()?,
+            "Tr" => entry.dissolve = 1.0 - number()?,
+            "illum" => entry.illum = number()? as u32,
+            _ => {}
+        }
+    }
+
+    if let Some((name, entry)) = current.take() {
+        materials.insert(name, build(&entry));
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::SolidColor;
+    use crate::{Color, Lambertian};
+
+    fn faces(obj: &str) -> Result<Vec<Triangle>, String> {
+        let material = Arc::new(Lambertian {
+            albedo: Arc::new(SolidColor { color: Color { r: 0.5, g: 0.5, b: 0.5, a: 1.0 } }),
+        });
+        parse_faces(obj, &HashMap::new(), material)
+    }
+
+    // the x coordinates of the triangles' corners, the test files below put vertex i at x = i
+    fn corners(triangles: &[Triangle]) -> Vec<[f64; 3]> {
+        triangles.iter().map(|t| [t.vertices[0].x, t.vertices[1].x, t.vertices[2].x]).collect()
+    }
+
+    const FIVE_VERTICES: &str = "v 1 0 0\nv 2 0 0\nv 3 1 0\nv 4 1 0\nv 5 2 0\n";
+
+    #[test]
+    fn indices() {
+        assert_eq!(resolve_index("1", 3), Ok(0));
+        assert_eq!(resolve_index("3", 3), Ok(2));
+        assert_eq!(resolve_index("-1", 3), Ok(2));
+        assert_eq!(resolve_index("-3", 3), Ok(0));
+        assert!(resolve_index("0", 3).is_err());
+        assert!(resolve_index("4", 3).is_err());
+        assert!(resolve_index("-4", 3).is_err());
+        assert!(resolve_index("x", 3).is_err());
+    }
+
+    // negative indices count back from the vertices read so far, not from the end of the file
+    #[test]
+    fn relative_indices() {
+        let triangles = faces(&format!("v 1 0 0\nv 2 0 0\nv 3 1 0\nf -3 -2 -1\n{}f -3 -2 -1\n", FIVE_VERTICES)).unwrap();
+        assert_eq!(corners(&triangles), [[1.0, 2.0, 3.0], [3.0, 4.0, 5.0]]);
+    }
+
+    #[test]
+    fn polygons_become_fans() {
+        let triangles = faces(&format!("{}f 1 2 3 4 5\n", FIVE_VERTICES)).unwrap();
+        assert_eq!(corners(&triangles), [[1.0, 2.0, 3.0], [1.0, 3.0, 4.0], [1.0, 4.0, 5.0]]);
+    }
+
+    #[test]
+    fn normals_without_texcoords() {
+        let triangles = faces(&format!("{}vn 0 0 1\nvn 0 1 0\nf 1//1 2//2 3//1\n", FIVE_VERTICES)).unwrap();
+        assert!(triangles[0].uvs.is_none());
+        let normals = triangles[0].normals.as_ref().unwrap();
+        assert_eq!(normals.iter().map(|n| n.y).collect::<Vec<_>>(), [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn texcoords_without_normals() {
+        let triangles = faces(&format!("{}vt 0.5 0.25\nvt 1\nf 1/1 2/2 3/1\n", FIVE_VERTICES)).unwrap();
+        assert!(triangles[0].normals.is_none());
+        assert_eq!(triangles[0].uvs, Some([(0.5, 0.25), (1.0, 0.0), (0.5, 0.25)]));
+    }
+
+    #[test]
+    fn errors_say_where() {
+        let error = |obj: &str| faces(&format!("{}{}", FIVE_VERTICES, obj)).err().unwrap();
+        assert_eq!(error("f 1 2 6\n"), "line 6: index 6 is out of range");
+        assert_eq!(error("f 1 2 -6\n"), "line 6: index -6 is out of range");
+        assert_eq!(error("f 1//1 2//1 3//1\n"), "line 6: index 1 is out of range");
+        assert_eq!(error("vt 0 0\nf 1/2 2/1 3/1\n"), "line 7: index 2 is out of range");
+        assert_eq!(error("f 1 2\n"), "line 6: a face needs at least three vertices");
+        assert_eq!(error("f 1 a 3\n"), "line 6: bad index \"a\"");
+    }
+}
@@ -0,0 +1,174 @@
+// perlin noise and the procedural textures built from it. everything only depends on the seed
+// so the same scene always looks the same, no matter the platform or the render's own seed
+use crate::rng::Pcg32;
+use crate::texture::Texture;
+use crate::{dot, Color, Vec3};
+
+const POINT_COUNT: usize = 256;
+
+pub(crate) struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub(crate) fn new(seed: u64) -> Perlin {
+        let mut rng = Pcg32::new(seed, 0);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| {
+                // random unit vectors so the lattice points don't all line up
+                loop {
+                    let v = Vec3 {
+                        x: 2.0 * rng.next_f64() - 1.0,
+                        y: 2.0 * rng.next_f64() - 1.0,
+                        z: 2.0 * rng.next_f64() - 1.0,
+                    };
+                    let length = v.length();
+                    if length > 1e-3 && length <= 1.0 {
+                        return v / length;
+                    }
+                }
+            })
+            .collect();
+
+        Perlin {
+            gradients,
+            perm_x: permutation(&mut rng),
+            perm_y: permutation(&mut rng),
+            perm_z: permutation(&mut rng),
+        }
+    }
+
+    // smooth noise roughly in [-1, 1]
+    pub(crate) fn noise(&self, p: &Vec3) -> f64 {
+        let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (u, v, w) = (p.x - fx, p.y - fy, p.z - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+
+        // the gradients at the eight corners of the lattice cell
+        let mut corners = [[[0.0; 2]; 2]; 2];
+        for (di, plane) in corners.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, value) in row.iter_mut().enumerate() {
+                    let index = self.perm_x[((i + di as i64) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i64) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i64) & 255) as usize];
+                    let weight = Vec3 { x: u - di as f64, y: v - dj as f64, z: w - dk as f64 };
+                    *value = dot(&self.gradients[index], &weight);
+                }
+            }
+        }
+
+        // hermite smoothing so there are no visible creases at the cell borders, then trilinear
+        let (uu, vv, ww) = (hermite(u), hermite(v), hermite(w));
+        let mut accum = 0.0;
+        for (di, plane) in corners.iter().enumerate() {
+            for (dj, row) in plane.iter().enumerate() {
+                for (dk, value) in row.iter().enumerate() {
+                    let (di, dj, dk) = (di as f64, dj as f64, dk as f64);
+                    accum += (di * uu + (1.0 - di) * (1.0 - uu))
+                        * (dj * vv + (1.0 - dj) * (1.0 - vv))
+                        * (dk * ww + (1.0 - dk) * (1.0 - ww))
+                        * value;
+                }
+            }
+        }
+        accum
+    }
+
+    // sum of octaves with halving weight and doubling frequency, always positive
+    pub(crate) fn turbulence(&self, p: &Vec3, depth: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut p = p.clone();
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            accum += weight * self.noise(&p);
+            weight *= 0.5;
+            p = 2.0 * p;
+        }
+        accum.abs()
+    }
+}
+
+fn permutation(rng: &mut Pcg32) -> Vec<usize> {
+    let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+    for i in (1..POINT_COUNT).rev() {
+        p.swap(i, rng.below(i as u32 + 1) as usize);
+    }
+    p
+}
+
+fn hermite(t: f64) -> f64 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn mix(a: &Color, b: &Color, t: f64) -> Color {
+    (1.0 - t) * a.clone() + t * b.clone()
+}
+
+// plain noise mapped to [0, 1]
+pub(crate) struct NoiseTexture {
+    pub(crate) perlin: Perlin,
+    pub(crate) scale: f64,
+    pub(crate) color: Color,
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, point: &Vec3) -> Color {
+        let n = 0.5 * (1.0 + self.perlin.noise(&(self.scale * point)));
+        n * self.color.clone()
+    }
+}
+
+pub(crate) struct TurbulenceTexture {
+    pub(crate) perlin: Perlin,
+    pub(crate) scale: f64,
+    pub(crate) depth: u32,
+    pub(crate) color: Color,
+}
+
+impl Texture for TurbulenceTexture {
+    fn value(&self, _u: f64, _v: f64, point: &Vec3) -> Color {
+        let t = self.perlin.turbulence(&(self.scale * point), self.depth).min(1.0);
+        t * self.color.clone()
+    }
+}
+
+// stripes along z that turbulence bends into veins
+pub(crate) struct Marble {
+    pub(crate) perlin: Perlin,
+    pub(crate) scale: f64,
+    pub(crate) turbulence: f64,
+    pub(crate) depth: u32,
+    pub(crate) light: Color,
+    pub(crate) dark: Color,
+}
+
+impl Texture for Marble {
+    fn value(&self, _u: f64, _v: f64, point: &Vec3) -> Color {
+        let phase = self.scale * point.z + self.turbulence * self.perlin.turbulence(point, self.depth);
+        mix(&self.dark, &self.light, 0.5 * (1.0 + libm::sin(phase)))
+    }
+}
+
+// rings around the y axis, wobbled a bit by noise so they aren't perfect circles
+pub(crate) struct Wood {
+    pub(crate) perlin: Perlin,
+    // rings per unit
+    pub(crate) scale: f64,
+    pub(crate) turbulence: f64,
+    pub(crate) light: Color,
+    pub(crate) dark: Color,
+}
+
+impl Texture for Wood {
+    fn value(&self, _u: f64, _v: f64, point: &Vec3) -> Color {
+        let distance = (point.x * point.x + point.z * point.z).sqrt();
+        let rings = self.scale * distance + self.turbulence * self.perlin.noise(&(4.0 * point));
+        // sharpen the ring edges a bit so it looks less like a sine wave
+        let t = libm::pow(rings - rings.floor(), 3.0);
+        mix(&self.light, &self.dark, t)
+    }
+}
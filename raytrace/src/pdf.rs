@@ -0,0 +1,121 @@
+// probability densities over directions, for picking where a ray goes next. materials have one
+// for their bounces and lights have one for the directions that hit them, which lets the renderer
+// aim rays at lights and weigh the two against each other
+use std::f64::consts::PI;
+
+use crate::environment::EnvironmentMap;
+use crate::sampler::Sampler;
+use crate::shapes::tangents;
+use crate::{dot, Background, Hitable, HitableList, Vec3};
+
+pub(crate) trait Pdf {
+    // density per solid angle of picking direction, which doesn't need to be normalized
+    fn value(&self, direction: &Vec3) -> f64;
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3;
+}
+
+// more rays close to the normal, the way a lambertian surface scatters light
+pub(crate) struct CosinePdf {
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+}
+
+impl CosinePdf {
+    pub(crate) fn new(normal: &Vec3) -> CosinePdf {
+        let normal = normal.normalize();
+        let (tangent, bitangent) = tangents(&normal);
+        CosinePdf { normal, tangent, bitangent }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine = dot(&direction.normalize(), &self.normal);
+        cosine.max(0.0) / PI
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        // a uniform point on the disk projected up onto the hemisphere
+        let (r1, r2) = sampler.next_2d();
+        let phi = 2.0 * PI * r1;
+        let x = libm::cos(phi) * r2.sqrt();
+        let y = libm::sin(phi) * r2.sqrt();
+        let z = (1.0 - r2).sqrt();
+        x * &self.tangent + y * &self.bitangent + z * &self.normal
+    }
+}
+
+// directions from origin towards a hitable, see Hitable::pdf_value and Hitable::random
+pub(crate) struct HitablePdf<'a> {
+    pub(crate) hitable: &'a dyn Hitable,
+    pub(crate) origin: Vec3,
+}
+
+impl Pdf for HitablePdf<'_> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.hitable.pdf_value(&self.origin, direction)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.hitable.random(&self.origin, sampler)
+    }
+}
+
+// everything shadow rays can aim at: the emitting shapes and the environment map if it's importance
+// sampled, half the time each when there are both
+pub(crate) struct LightPdf<'a> {
+    shapes: Option<HitablePdf<'a>>,
+    environment: Option<&'a EnvironmentMap>,
+}
+
+impl<'a> LightPdf<'a> {
+    pub(crate) fn new(lights: &'a HitableList, background: &'a Background, origin: &Vec3) -> LightPdf<'a> {
+        LightPdf {
+            shapes: if lights.hitables.is_empty() {
+                None
+            } else {
+                Some(HitablePdf { hitable: lights, origin: origin.clone() })
+            },
+            environment: match background {
+                Background::Environment(environment) if environment.is_sampled() => Some(environment),
+                _ => None,
+            },
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.shapes.is_none() && self.environment.is_none()
+    }
+}
+
+impl Pdf for LightPdf<'_> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        match (&self.shapes, self.environment) {
+            (Some(shapes), Some(environment)) => 0.5 * shapes.value(direction) + 0.5 * environment.pdf_value(direction),
+            (Some(shapes), None) => shapes.value(direction),
+            (None, Some(environment)) => environment.pdf_value(direction),
+            (None, None) => 0.0,
+        }
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        match (&self.shapes, self.environment) {
+            (Some(shapes), Some(environment)) => {
+                if sampler.next_1d() < 0.5 { shapes.generate(sampler) } else { environment.random(sampler) }
+            }
+            (Some(shapes), None) => shapes.generate(sampler),
+            (None, Some(environment)) => environment.random(sampler),
+            (None, None) => random_unit_vector(sampler),
+        }
+    }
+}
+
+// uniform over all directions
+pub(crate) fn random_unit_vector(sampler: &mut dyn Sampler) -> Vec3 {
+    let (r1, r2) = sampler.next_2d();
+    let z = 1.0 - 2.0 * r1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * r2;
+    Vec3 { x: r * libm::cos(phi), y: r * libm::sin(phi), z }
+}
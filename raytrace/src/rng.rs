@@ -0,0 +1,70 @@
+// all the randomness in a render comes from here. the generators in rand may give different
+// numbers on different platforms or versions, this one gives the same everywhere
+//
+// pcg32 (xsh rr) by melissa o'neill, see https://www.pcg-random.org
+pub(crate) struct Pcg32 {
+    state: u64,
+    // odd, picks one of 2^63 independent sequences
+    increment: u64,
+}
+
+impl Pcg32 {
+    pub(crate) fn new(seed: u64, stream: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: 0, increment: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    // every pixel gets a stream of its own and every sample its own place in it, so what a sample
+    // sees doesn't depend on the thread that renders it or on what got rendered before
+    pub(crate) fn for_sample(seed: u64, pixel: usize, sample: u32) -> Pcg32 {
+        Pcg32::new(sample_seed(seed, pixel, sample), pixel as u64)
+    }
+
+    pub(crate) fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    // 0..bound, every one equally likely. the numbers below 2^32 % bound get thrown away, they'd
+    // make the low results come up a bit more often than the rest. from the pcg reference code
+    pub(crate) fn below(&mut self, bound: u32) -> u32 {
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let r = self.next_u32();
+            if r >= threshold {
+                return r % bound;
+            }
+        }
+    }
+
+    // [0, 1) with all 53 bits a f64 has
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        let high = (self.next_u32() as u64) << 21;
+        let low = (self.next_u32() as u64) >> 11;
+        (high | low) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// so that neighbouring pixels and samples don't end up with similar seeds
+fn sample_seed(seed: u64, pixel: usize, sample: u32) -> u64 {
+    mix(seed
+        .wrapping_add((pixel as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+        .wrapping_add((sample as u64).wrapping_mul(0xd1b5_4a32_d192_ed03)))
+}
+
+// one well mixed number out of a few, for seeding things per pixel and dimension
+pub(crate) fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0, |h, &value| mix(h ^ value.wrapping_add(0x9e37_79b9_7f4a_7c15)))
+}
+
+// the finalizer of splitmix64, flipping one bit of z flips about half of the result
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
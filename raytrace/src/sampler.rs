@@ -0,0 +1,355 @@
+// where the random numbers for a sample come from. plain random numbers clump together and leave
+// gaps, the other samplers spread the samples of a pixel out more evenly so the same number of
+// them gives less noise
+//
+// every sample asks for its numbers in the same order, the pixel offset first, then the lens,
+// then whatever the bounces need. each of those is a dimension, and the samplers make the
+// samples of a pixel well spread in every dimension and in every pair they ask for together
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::rng::{hash, Pcg32};
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    // white noise, every number on its own
+    Independent,
+    // splits every dimension into as many strata as there are samples per pixel and puts one
+    // sample in each of them, randomly placed inside
+    Stratified,
+    // the halton sequence, scrambled differently in every pixel
+    Halton,
+    // the sobol sequence with owen scrambling, usually the least noisy
+    Sobol,
+}
+
+pub(crate) trait Sampler {
+    // goes back to the first dimension
+    fn start_sample(&mut self, pixel: usize, sample: u32);
+    // [0, 1)
+    fn next_1d(&mut self) -> f64;
+    // a pair that is spread out well in both dimensions together
+    fn next_2d(&mut self) -> (f64, f64);
+}
+
+// samplers aren't shared between threads, every pixel makes its own
+pub(crate) fn make_sampler(kind: SamplerKind, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+    match kind {
+        SamplerKind::Independent => Box::new(Independent { seed, rng: Pcg32::new(seed, 0) }),
+        SamplerKind::Stratified => Box::new(Stratified {
+            seed,
+            samples_per_pixel: samples_per_pixel.max(1),
+            pixel: 0,
+            round: 0,
+            index: 0,
+            dimension: 0,
+            rng: Pcg32::new(seed, 0),
+        }),
+        SamplerKind::Halton => Box::new(Halton {
+            seed,
+            samples_per_pixel,
+            pixel: 0,
+            sample: 0,
+            dimension: 0,
+            rng: Pcg32::new(seed, 0),
+        }),
+        SamplerKind::Sobol => Box::new(Sobol { seed, pixel: 0, sample: 0, dimension: 0 }),
+    }
+}
+
+// the largest f64 below 1
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+struct Independent {
+    seed: u64,
+    rng: Pcg32,
+}
+
+impl Sampler for Independent {
+    fn start_sample(&mut self, pixel: usize, sample: u32) {
+        self.rng = Pcg32::for_sample(self.seed, pixel, sample);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        self.rng.next_f64()
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.rng.next_f64(), self.rng.next_f64())
+    }
+}
+
+// every dimension gets its own shuffle of the strata so they don't line up with each other.
+// samples past samples_per_pixel start another round with new shuffles
+struct Stratified {
+    seed: u64,
+    samples_per_pixel: u32,
+    pixel: usize,
+    round: u32,
+    // which of the samples of the round this is
+    index: u32,
+    dimension: u32,
+    // for the jitter inside the strata
+    rng: Pcg32,
+}
+
+impl Stratified {
+    // the stratum this sample gets in the current dimension, out of count of them
+    fn stratum(&mut self, count: u32) -> u32 {
+        let shuffle = hash(&[self.seed, self.pixel as u64, self.round as u64, self.dimension as u64]);
+        self.dimension += 1;
+        permute(self.index, count, shuffle as u32)
+    }
+}
+
+impl Sampler for Stratified {
+    fn start_sample(&mut self, pixel: usize, sample: u32) {
+        self.pixel = pixel;
+        self.round = sample / self.samples_per_pixel;
+        self.index = sample % self.samples_per_pixel;
+        self.dimension = 0;
+        self.rng = Pcg32::for_sample(self.seed, pixel, sample);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let n = self.samples_per_pixel;
+        let stratum = self.stratum(n);
+        ((stratum as f64 + self.rng.next_f64()) / n as f64).min(ONE_MINUS_EPSILON)
+    }
+
+    // a grid as close to square as it gets. when the samples don't fill it every round picks a
+    // different random few of the cells to leave out, so in the long run every part of the square
+    // gets its share
+    fn next_2d(&mut self) -> (f64, f64) {
+        let n = self.samples_per_pixel;
+        let columns = (n as f64).sqrt().ceil() as u32;
+        let rows = n.div_ceil(columns);
+        let cell = self.stratum(columns * rows);
+        let x = ((cell % columns) as f64 + self.rng.next_f64()) / columns as f64;
+        let y = ((cell / columns) as f64 + self.rng.next_f64()) / rows as f64;
+        (x.min(ONE_MINUS_EPSILON), y.min(ONE_MINUS_EPSILON))
+    }
+}
+
+// a different prime per dimension. past the last one the numbers are just random
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+];
+
+// the digits get scrambled (owen scrambling again) so neighbouring pixels don't get the same
+// numbers. it also breaks up the higher dimensions, whose first few samples all sit close together
+// since their primes are so large
+struct Halton {
+    seed: u64,
+    samples_per_pixel: u32,
+    pixel: usize,
+    sample: u32,
+    dimension: u32,
+    rng: Pcg32,
+}
+
+impl Sampler for Halton {
+    fn start_sample(&mut self, pixel: usize, sample: u32) {
+        self.pixel = pixel;
+        self.sample = sample;
+        self.dimension = 0;
+        self.rng = Pcg32::for_sample(self.seed, pixel, sample);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let dimension = self.dimension as usize;
+        self.dimension += 1;
+        if dimension >= PRIMES.len() {
+            return self.rng.next_f64();
+        }
+        let scramble = hash(&[self.seed, self.pixel as u64, dimension as u64]);
+        scrambled_radical_inverse(PRIMES[dimension], self.sample, self.samples_per_pixel, scramble)
+    }
+
+    // consecutive primes already go well together
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+// the digits of n in the base mirrored around the point, 1234 becomes 0.4321. every digit goes
+// through a permutation picked by the digits before it. the zeros past the end of n get scrambled
+// too, but only as many as it takes to tell count samples apart. all that's left to do below
+// that is to land somewhere random in the tiny interval the digits picked, one hash does that
+// as well as scrambling the rest of the 53 bits digit by digit, and for a fraction of the work
+fn scrambled_radical_inverse(base: u32, mut n: u32, count: u32, scramble: u64) -> f64 {
+    let base = base as u64;
+    let inverse_base = 1.0 / base as f64;
+    let mut reversed: u64 = 0;
+    let mut scale = 1.0;
+    // base to the power of the digits so far
+    let mut covered: u64 = 1;
+    while covered < count as u64 || n != 0 {
+        let next = n as u64 / base;
+        let digit = n as u64 - next * base;
+        let digit = permute(digit as u32, base as u32, hash(&[scramble, reversed]) as u32);
+        reversed = reversed * base + digit as u64;
+        scale *= inverse_base;
+        covered *= base;
+        n = next as u32;
+    }
+    let tail = (hash(&[scramble, reversed]) >> 11) as f64 / (1u64 << 53) as f64;
+    ((reversed as f64 + tail) * scale).min(ONE_MINUS_EPSILON)
+}
+
+// only the first two sobol dimensions, every pair of dimensions asked for gets its own scramble
+// and its own shuffle of the sample order so they don't correlate. see "practical hash-based owen
+// scrambling" by brent burley
+struct Sobol {
+    seed: u64,
+    pixel: usize,
+    sample: u32,
+    dimension: u32,
+}
+
+impl Sobol {
+    fn scrambled(&mut self) -> (u32, u32) {
+        let seed = hash(&[self.seed, self.pixel as u64, self.dimension as u64]);
+        self.dimension += 1;
+        let index = nested_uniform_scramble(self.sample, seed as u32);
+        let x = nested_uniform_scramble(sobol_first(index), (seed >> 32) as u32);
+        let y = nested_uniform_scramble(sobol_second(index), hash(&[seed]) as u32);
+        (x, y)
+    }
+}
+
+impl Sampler for Sobol {
+    fn start_sample(&mut self, pixel: usize, sample: u32) {
+        self.pixel = pixel;
+        self.sample = sample;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        to_unit(self.scrambled().0)
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let (x, y) = self.scrambled();
+        (to_unit(x), to_unit(y))
+    }
+}
+
+// the first sobol dimension is the van der corput sequence
+fn sobol_first(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+fn sobol_second(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+// owen scrambling flips the bits based on all the bits above them. this hash by laine and karras
+// does that for the low bits, so it gets used on the bits in reverse
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 / (1u64 << 32) as f64
+}
+
+// a random permutation of 0..count picked by seed, without making a list of it. by andrew kensler,
+// from "correlated multi-jittered sampling"
+fn permute(mut i: u32, count: u32, seed: u32) -> u32 {
+    let mut w = count - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    // the hash permutes the next power of two, anything past count just goes around again
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < count {
+            break;
+        }
+    }
+    ((i as u64 + seed as u64) % count as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3 samples make a 2x2 grid, the cell the samples leave out mustn't always be the same one
+    #[test]
+    fn stratified_2d_covers_the_square() {
+        let mut sampler = make_sampler(SamplerKind::Stratified, 7, 3);
+        let mut quadrants = [0; 4];
+        for pixel in 0..1000 {
+            for sample in 0..3 {
+                sampler.start_sample(pixel, sample);
+                let (x, y) = sampler.next_2d();
+                quadrants[(x >= 0.5) as usize + 2 * (y >= 0.5) as usize] += 1;
+            }
+        }
+        // 750 each on average
+        for count in quadrants {
+            assert!(count > 600, "{:?}", quadrants);
+        }
+    }
+
+    // the random tail mustn't undo the digits, the first 8 samples of the base 2 dimension still
+    // get one eighth each and the 9 of base 3 one ninth each
+    #[test]
+    fn halton_samples_stay_stratified() {
+        for pixel in 0..100 {
+            let mut sampler = make_sampler(SamplerKind::Halton, 7, 8);
+            let mut eighths = [0; 8];
+            let mut ninths = [0; 9];
+            for sample in 0..9 {
+                sampler.start_sample(pixel, sample);
+                let (x, y) = sampler.next_2d();
+                if sample < 8 {
+                    eighths[(x * 8.0) as usize] += 1;
+                }
+                ninths[(y * 9.0) as usize] += 1;
+            }
+            assert_eq!(eighths, [1; 8]);
+            assert_eq!(ninths, [1; 9]);
+        }
+    }
+}
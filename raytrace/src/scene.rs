@@ -0,0 +1,641 @@
+// json scene files. materials are declared once under a name and objects refer to them,
+// everything except the objects has defaults so a scene can be pretty short.
+// kinds of objects and materials are written as { "sphere": { ... } } instead of a "type"
+// field, serde can't tell which field was wrong inside an internally tagged enum
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::adaptive::Adaptive;
+use crate::aov::{NumberedMaterial, NumberedObject};
+use crate::bvh::BvhNode;
+use crate::environment::EnvironmentMap;
+use crate::mesh::{Mesh, Triangle};
+use crate::noise::{Marble, NoiseTexture, Perlin, TurbulenceTexture, Wood};
+use crate::sampler::SamplerKind;
+use crate::shapes::{Cuboid, Cylinder, Disk, Plane, Rect};
+use crate::texture::{Checker, Image, ImageTexture, SolidColor, Texture};
+use crate::tonemap::ToneMap;
+use crate::transform::Transform;
+use crate::{Background, Camera, Color, Dielectric, DiffuseLight, Hitable, HitableList, Lambertian, Material, Metal, Sphere, TileOrder, Vec3};
+
+pub(crate) const DEFAULT_SCENE: &str = include_str!("../scenes/default.json");
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDescription {
+    #[serde(default)]
+    settings: RenderSettings,
+    #[serde(default)]
+    camera: CameraDescription,
+    #[serde(default)]
+    background: BackgroundDescription,
+    #[serde(default)]
+    textures: HashMap<String, TextureDescription>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDescription>,
+    // objects that only show up in the scene through instances
+    #[serde(default)]
+    definitions: HashMap<String, ObjectDescription>,
+    objects: Vec<ObjectDescription>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RenderSettings {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) samples_per_pixel: u32,
+    pub(crate) seed: u64,
+    pub(crate) sampler: SamplerKind,
+    // bounces per path at most, russian roulette ends most of them long before
+    pub(crate) max_depth: u32,
+    pub(crate) tile_order: TileOrder,
+    // false tests every object for every ray, only useful for comparing
+    pub(crate) bvh: bool,
+    pub(crate) tone_map: ToneMap,
+    // in stops
+    pub(crate) exposure: f64,
+    // None for the srgb curve
+    pub(crate) gamma: Option<f64>,
+    // filters the noise out of the finished picture
+    pub(crate) denoise: bool,
+    // None gives every pixel samples_per_pixel samples, otherwise that's only the average
+    pub(crate) adaptive: Option<Adaptive>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            width: 200,
+            height: 100,
+            samples_per_pixel: 100,
+            seed: 0,
+            sampler: SamplerKind::Sobol,
+            max_depth: 50,
+            tile_order: TileOrder::Spiral,
+            bvh: true,
+            tone_map: ToneMap::Clamp,
+            exposure: 0.0,
+            gamma: None,
+            denoise: false,
+            adaptive: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CameraDescription {
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    vup: [f64; 3],
+    vfov: f64,
+    aperture: f64,
+    // defaults to the distance to look_at
+    focus_dist: Option<f64>,
+}
+
+impl Default for CameraDescription {
+    fn default() -> Self {
+        CameraDescription {
+            look_from: [0.0, 0.0, 0.0],
+            look_at: [0.0, 0.0, -1.0],
+            vup: [0.0, 1.0, 0.0],
+            vfov: 90.0,
+            aperture: 0.0,
+            focus_dist: None,
+        }
+    }
+}
+
+// "sky", { "color": [r, g, b] } or { "environment": { "image": "name", ... } }
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDescription {
+    #[default]
+    Sky,
+    Color([f64; 3]),
+    Environment {
+        image: String,
+        // degrees around the up axis
+        #[serde(default)]
+        rotation: f64,
+        #[serde(default = "default_importance_sampling")]
+        importance_sampling: bool,
+    },
+}
+
+fn default_importance_sampling() -> bool {
+    true
+}
+
+// wherever a texture goes a plain color works too
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureReference {
+    Color([f64; 3]),
+    Named(String),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDescription {
+    Solid { color: [f64; 3] },
+    Checker {
+        odd: TextureReference,
+        even: TextureReference,
+        #[serde(default = "default_checker_scale")]
+        scale: f64,
+    },
+    // the name an image was added under with Canvas::add_image
+    Image { image: String },
+    // procedural ones, the seed picks the noise pattern
+    Noise {
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_noise_scale")]
+        scale: f64,
+        #[serde(default = "default_white")]
+        color: [f64; 3],
+    },
+    Turbulence {
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_noise_scale")]
+        scale: f64,
+        #[serde(default = "default_depth")]
+        depth: u32,
+        #[serde(default = "default_white")]
+        color: [f64; 3],
+    },
+    Marble {
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_noise_scale")]
+        scale: f64,
+        #[serde(default = "default_marble_turbulence")]
+        turbulence: f64,
+        #[serde(default = "default_depth")]
+        depth: u32,
+        #[serde(default = "default_white")]
+        light: [f64; 3],
+        #[serde(default)]
+        dark: [f64; 3],
+    },
+    Wood {
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_wood_scale")]
+        scale: f64,
+        #[serde(default = "default_wood_turbulence")]
+        turbulence: f64,
+        #[serde(default = "default_wood_light")]
+        light: [f64; 3],
+        #[serde(default = "default_wood_dark")]
+        dark: [f64; 3],
+    },
+}
+
+fn default_checker_scale() -> f64 {
+    10.0
+}
+
+fn default_noise_scale() -> f64 {
+    4.0
+}
+
+fn default_depth() -> u32 {
+    7
+}
+
+fn default_white() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_marble_turbulence() -> f64 {
+    10.0
+}
+
+fn default_wood_scale() -> f64 {
+    8.0
+}
+
+fn default_wood_turbulence() -> f64 {
+    0.3
+}
+
+fn default_wood_light() -> [f64; 3] {
+    [0.8, 0.6, 0.35]
+}
+
+fn default_wood_dark() -> [f64; 3] {
+    [0.45, 0.28, 0.12]
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDescription {
+    Lambertian { albedo: TextureReference },
+    Metal { albedo: TextureReference, #[serde(default)] fuzz: f64 },
+    Dielectric { ref_idx: f64 },
+    DiffuseLight { emit: TextureReference },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDescription {
+    Sphere { center: [f64; 3], radius: f64, material: String },
+    // normals and uvs are optional, one per vertex
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        #[serde(default)]
+        normals: Option<[[f64; 3]; 3]>,
+        #[serde(default)]
+        uvs: Option<[[f64; 2]; 3]>,
+        material: String,
+    },
+    // infinite, stays out of the bvh
+    Plane { point: [f64; 3], normal: [f64; 3], material: String },
+    // min and max have to be the same on exactly one axis, that's the one the normal points along
+    Rect {
+        min: [f64; 3],
+        max: [f64; 3],
+        #[serde(default)]
+        flip: bool,
+        material: String,
+    },
+    Box { min: [f64; 3], max: [f64; 3], material: String },
+    Disk { center: [f64; 3], normal: [f64; 3], radius: f64, material: String },
+    Cylinder {
+        base: [f64; 3],
+        top: [f64; 3],
+        radius: f64,
+        #[serde(default = "default_capped")]
+        capped: bool,
+        material: String,
+    },
+    // one of the meshes added with Renderer::add_mesh, with the materials from its mtl file
+    Mesh { name: String },
+    // places one of the definitions, the transforms get applied in order
+    Instance {
+        of: String,
+        #[serde(default)]
+        transforms: Vec<TransformDescription>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDescription {
+    Translate([f64; 3]),
+    // in degrees
+    RotateX(f64),
+    RotateY(f64),
+    RotateZ(f64),
+    Scale([f64; 3]),
+}
+
+fn default_capped() -> bool {
+    true
+}
+
+pub(crate) struct Scene {
+    pub(crate) settings: RenderSettings,
+    pub(crate) camera: Camera,
+    pub(crate) background: Background,
+    pub(crate) world: HitableList,
+    pub(crate) lights: HitableList,
+    // how many objects got numbered
+    pub(crate) objects: u32,
+}
+
+// errors start with the path of the field that's wrong, e.g. "objects[2].radius: ..."
+// images are the ones image textures can use and meshes the ones mesh objects can, by name
+pub(crate) fn parse(json: &str, images: &HashMap<String, Arc<Image>>, meshes: &HashMap<String, Mesh>) -> Result<Scene, String> {
+    let deserializer = &mut serde_json::Deserializer::from_str(json);
+    let description: SceneDescription = serde_path_to_error::deserialize(deserializer)
+        .map_err(|err| format!("{}: {}", err.path(), err.inner()))?;
+
+    let mut textures = Textures {
+        descriptions: &description.textures,
+        images,
+        built: HashMap::new(),
+        building: Vec::new(),
+    };
+    // numbered in alphabetical order for the material id aov, starting at 1
+    let mut names: Vec<&String> = description.materials.keys().collect();
+    names.sort();
+    let mut materials: HashMap<&str, Arc<dyn Material>> = HashMap::new();
+    for (i, name) in names.into_iter().enumerate() {
+        let path = format!("materials.{}", name);
+        let material = build_material(&description.materials[name], &path, &mut textures)?;
+        materials.insert(name.as_str(), Arc::new(NumberedMaterial { id: i as u32 + 1, material }));
+    }
+
+    // definitions are built once and can then be placed any number of times with instances
+    let mut definitions: HashMap<&str, Arc<dyn Hitable>> = HashMap::new();
+    for (name, object) in description.definitions.iter() {
+        let path = format!("definitions.{}", name);
+        let hitable = build_object(object, &path, &materials, &HashMap::new(), meshes)?;
+        definitions.insert(name.as_str(), Arc::from(hitable));
+    }
+
+    let mut hitables: Vec<Box<dyn Hitable>> = Vec::new();
+    let mut lights: Vec<Box<dyn Hitable>> = Vec::new();
+    for (i, object) in description.objects.iter().enumerate() {
+        let path = format!("objects[{}]", i);
+        let hitable = build_object(object, &path, &materials, &definitions, meshes)?;
+        // numbered in order for the object id aov, starting at 1
+        hitables.push(Box::new(NumberedObject { id: i as u32 + 1, hitable }));
+        // glowing spheres, rects and disks, placed as they are or through an instance, also get a
+        // copy the renderer can aim shadow rays at. other lights still work, they're just noisier
+        let shape = match object {
+            ObjectDescription::Instance { of, .. } => &description.definitions[of],
+            _ => object,
+        };
+        if is_sampled_light(shape, &description.materials) {
+            lights.push(build_object(object, &path, &materials, &definitions, meshes)?);
+        }
+    }
+
+    let settings = description.settings;
+    if settings.width == 0 || settings.height == 0 {
+        return Err("settings: width and height have to be at least 1".to_string());
+    }
+    if let Some(gamma) = settings.gamma {
+        if gamma.is_nan() || gamma <= 0.0 {
+            return Err("settings.gamma: the gamma has to be above 0".to_string());
+        }
+    }
+    if let Some(adaptive) = &settings.adaptive {
+        adaptive.check().map_err(|err| format!("settings.adaptive: {}", err))?;
+    }
+
+    let camera = build_camera(&description.camera, settings.width as f64 / settings.height as f64)?;
+
+    let world = if settings.bvh {
+        // anything without a bounding box stays next to the tree
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = hitables.into_iter()
+            .partition(|hitable| hitable.bounding_box().is_some());
+        let mut world = HitableList { hitables: unbounded };
+        if let Some(bvh) = BvhNode::new(bounded) {
+            world.hitables.push(Box::new(bvh));
+        }
+        world
+    } else {
+        HitableList { hitables }
+    };
+
+    let background = match description.background {
+        BackgroundDescription::Sky => Background::Sky,
+        BackgroundDescription::Color(c) => Background::Solid(color(&c)),
+        BackgroundDescription::Environment { image, rotation, importance_sampling } => {
+            let image = images.get(&image)
+                .cloned()
+                .ok_or_else(|| format!("background.environment.image: no image called \"{}\" was added", image))?;
+            Background::Environment(EnvironmentMap::new(image, rotation, importance_sampling))
+        }
+    };
+
+    Ok(Scene {
+        settings,
+        camera,
+        background,
+        world,
+        lights: HitableList { hitables: lights },
+        objects: description.objects.len() as u32,
+    })
+}
+
+// the shapes that know how to pick directions towards themselves
+fn is_sampled_light(object: &ObjectDescription, materials: &HashMap<String, MaterialDescription>) -> bool {
+    let material = match object {
+        ObjectDescription::Sphere { material, .. }
+        | ObjectDescription::Rect { material, .. }
+        | ObjectDescription::Disk { material, .. } => material,
+        _ => return false,
+    };
+    matches!(materials.get(material), Some(MaterialDescription::DiffuseLight { .. }))
+}
+
+fn build_object(
+    object: &ObjectDescription,
+    path: &str,
+    materials: &HashMap<&str, Arc<dyn Material>>,
+    definitions: &HashMap<&str, Arc<dyn Hitable>>,
+    meshes: &HashMap<String, Mesh>,
+) -> Result<Box<dyn Hitable>, String> {
+    let find_material = |kind: &str, name: &str| {
+        materials.get(name)
+            .cloned()
+            .ok_or_else(|| format!("{}.{}.material: unknown material \"{}\"", path, kind, name))
+    };
+
+    let hitable: Box<dyn Hitable> = match object {
+        ObjectDescription::Sphere { center, radius, material } => {
+            // a negative radius is fine, it turns the normals inwards for hollow glass
+            if radius.is_nan() || *radius == 0.0 {
+                return Err(format!("{}.sphere.radius: the radius can't be 0", path));
+            }
+            Box::new(Sphere {
+                center: vec3(center),
+                radius: *radius,
+                material: find_material("sphere", material)?,
+            })
+        }
+        ObjectDescription::Triangle { vertices, normals, uvs, material } => Box::new(Triangle {
+            vertices: [vec3(&vertices[0]), vec3(&vertices[1]), vec3(&vertices[2])],
+            normals: normals.as_ref().map(|n| [vec3(&n[0]), vec3(&n[1]), vec3(&n[2])]),
+            uvs: uvs.map(|uv| [(uv[0][0], uv[0][1]), (uv[1][0], uv[1][1]), (uv[2][0], uv[2][1])]),
+            material: find_material("triangle", material)?,
+        }),
+        ObjectDescription::Plane { point, normal, material } => Box::new(Plane {
+            point: vec3(point),
+            normal: direction(normal, &format!("{}.plane.normal", path))?,
+            material: find_material("plane", material)?,
+        }),
+        ObjectDescription::Rect { min, max, flip, material } => {
+            let rect = Rect::new(vec3(min), vec3(max), *flip, find_material("rect", material)?)
+                .ok_or_else(|| format!("{}.rect: min and max have to be equal on exactly one axis", path))?;
+            Box::new(rect)
+        }
+        ObjectDescription::Box { min, max, material } => {
+            Box::new(Cuboid::new(vec3(min), vec3(max), find_material("box", material)?))
+        }
+        ObjectDescription::Disk { center, normal, radius, material } => Box::new(Disk {
+            center: vec3(center),
+            normal: direction(normal, &format!("{}.disk.normal", path))?,
+            radius: positive_radius(*radius, &format!("{}.disk.radius", path))?,
+            material: find_material("disk", material)?,
+        }),
+        ObjectDescription::Cylinder { base, top, radius, capped, material } => {
+            let (base, top) = (vec3(base), vec3(top));
+            if (&top - &base).length() == 0.0 {
+                return Err(format!("{}.cylinder: base and top have to be different points", path));
+            }
+            Box::new(Cylinder {
+                base,
+                top,
+                radius: positive_radius(*radius, &format!("{}.cylinder.radius", path))?,
+                capped: *capped,
+                material: find_material("cylinder", material)?,
+            })
+        }
+        ObjectDescription::Mesh { name } => {
+            let mesh = meshes.get(name)
+                .ok_or_else(|| format!("{}.mesh.name: unknown mesh \"{}\", it has to be added before the scene", path, name))?;
+            Box::new(mesh.clone())
+        }
+        ObjectDescription::Instance { of, transforms } => {
+            let hitable = definitions.get(of.as_str())
+                .cloned()
+                .ok_or_else(|| format!("{}.instance.of: unknown definition \"{}\"", path, of))?;
+            let mut transform = Transform::new(hitable);
+            for (j, step) in transforms.iter().enumerate() {
+                transform = match step {
+                    TransformDescription::Translate(offset) => transform.translate(&vec3(offset)),
+                    TransformDescription::RotateX(degrees) => transform.rotate_x(*degrees),
+                    TransformDescription::RotateY(degrees) => transform.rotate_y(*degrees),
+                    TransformDescription::RotateZ(degrees) => transform.rotate_z(*degrees),
+                    TransformDescription::Scale(scale) => transform.scale(&vec3(scale))
+                        .ok_or_else(|| format!("{}.instance.transforms[{}]: the scale flattens the object", path, j))?,
+                };
+            }
+            Box::new(transform)
+        }
+    };
+    Ok(hitable)
+}
+
+// a normal or axis, which has to point somewhere
+fn direction(v: &[f64; 3], path: &str) -> Result<Vec3, String> {
+    let v = vec3(v);
+    let length = v.length();
+    if length.is_nan() || length == 0.0 {
+        return Err(format!("{}: can't be 0", path));
+    }
+    Ok(v)
+}
+
+fn positive_radius(radius: f64, path: &str) -> Result<f64, String> {
+    if radius.is_nan() || radius <= 0.0 {
+        return Err(format!("{}: the radius has to be above 0", path));
+    }
+    Ok(radius)
+}
+
+fn build_camera(description: &CameraDescription, aspect: f64) -> Result<Camera, String> {
+    let look_from = vec3(&description.look_from);
+    let look_at = vec3(&description.look_at);
+    let focus_dist = description.focus_dist
+        .unwrap_or_else(|| (&look_from - &look_at).length());
+
+    let mut camera = Camera::new(look_from, &look_at, &vec3(&description.vup), description.vfov, aspect)
+        .map_err(|err| format!("camera: {}", err))?;
+    Camera::check_lens(description.aperture, focus_dist).map_err(|err| format!("camera: {}", err))?;
+    camera.set_lens(description.aperture, focus_dist);
+    Ok(camera)
+}
+
+fn build_material(description: &MaterialDescription, path: &str, textures: &mut Textures) -> Result<Arc<dyn Material>, String> {
+    let material: Arc<dyn Material> = match description {
+        MaterialDescription::Lambertian { albedo } => Arc::new(Lambertian {
+            albedo: textures.get(albedo, &format!("{}.lambertian.albedo", path))?,
+        }),
+        MaterialDescription::Metal { albedo, fuzz } => Arc::new(Metal {
+            albedo: textures.get(albedo, &format!("{}.metal.albedo", path))?,
+            fuzz: *fuzz,
+        }),
+        MaterialDescription::Dielectric { ref_idx } => {
+            if ref_idx.is_nan() || *ref_idx <= 0.0 {
+                return Err(format!("{}.dielectric.ref_idx: the refractive index has to be above 0", path));
+            }
+            Arc::new(Dielectric { ref_idx: *ref_idx })
+        }
+        MaterialDescription::DiffuseLight { emit } => Arc::new(DiffuseLight {
+            emit: textures.get(emit, &format!("{}.diffuse_light.emit", path))?,
+        }),
+    };
+    Ok(material)
+}
+
+// textures can be made of other textures, so they get built when something first needs them
+struct Textures<'a> {
+    descriptions: &'a HashMap<String, TextureDescription>,
+    images: &'a HashMap<String, Arc<Image>>,
+    built: HashMap<String, Arc<dyn Texture>>,
+    // to notice textures that contain themselves
+    building: Vec<String>,
+}
+
+impl<'a> Textures<'a> {
+    fn get(&mut self, reference: &TextureReference, path: &str) -> Result<Arc<dyn Texture>, String> {
+        let name = match reference {
+            TextureReference::Color(c) => return Ok(Arc::new(SolidColor { color: color(c) })),
+            TextureReference::Named(name) => name,
+        };
+        if let Some(texture) = self.built.get(name) {
+            return Ok(texture.clone());
+        }
+        if self.building.contains(name) {
+            return Err(format!("{}: texture \"{}\" contains itself", path, name));
+        }
+        let description = self.descriptions.get(name)
+            .ok_or_else(|| format!("{}: unknown texture \"{}\"", path, name))?;
+
+        self.building.push(name.clone());
+        let texture: Arc<dyn Texture> = match description {
+            TextureDescription::Solid { color: c } => Arc::new(SolidColor { color: color(c) }),
+            TextureDescription::Checker { odd, even, scale } => Arc::new(Checker {
+                odd: self.get(odd, &format!("textures.{}.checker.odd", name))?,
+                even: self.get(even, &format!("textures.{}.checker.even", name))?,
+                scale: *scale,
+            }),
+            TextureDescription::Image { image } => {
+                let image = self.images.get(image)
+                    .cloned()
+                    .ok_or_else(|| format!("textures.{}.image.image: no image called \"{}\" was added", name, image))?;
+                Arc::new(ImageTexture { image })
+            }
+            TextureDescription::Noise { seed, scale, color: c } => Arc::new(NoiseTexture {
+                perlin: Perlin::new(*seed),
+                scale: *scale,
+                color: color(c),
+            }),
+            TextureDescription::Turbulence { seed, scale, depth, color: c } => Arc::new(TurbulenceTexture {
+                perlin: Perlin::new(*seed),
+                scale: *scale,
+                depth: *depth,
+                color: color(c),
+            }),
+            TextureDescription::Marble { seed, scale, turbulence, depth, light, dark } => Arc::new(Marble {
+                perlin: Perlin::new(*seed),
+                scale: *scale,
+                turbulence: *turbulence,
+                depth: *depth,
+                light: color(light),
+                dark: color(dark),
+            }),
+            TextureDescription::Wood { seed, scale, turbulence, light, dark } => Arc::new(Wood {
+                perlin: Perlin::new(*seed),
+                scale: *scale,
+                turbulence: *turbulence,
+                light: color(light),
+                dark: color(dark),
+            }),
+        };
+        self.building.pop();
+
+        self.built.insert(name.clone(), texture.clone());
+        Ok(texture)
+    }
+}
+
+fn vec3(v: &[f64; 3]) -> Vec3 {
+    Vec3 { x: v[0], y: v[1], z: v[2] }
+}
+
+fn color(c: &[f64; 3]) -> Color {
+    Color { r: c[0], g: c[1], b: c[2], a: 1.0 }
+}
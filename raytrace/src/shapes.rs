@@ -0,0 +1,353 @@
+// analytic primitives besides spheres. normals point outwards like they do on spheres
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::bvh::Aabb;
+use crate::sampler::Sampler;
+use crate::{cross, dot, HitRecord, Hitable, HitableList, Material, Ray, Vec3};
+
+// the outside of the box is only this thick, flat things still need a box with some volume
+const PAD: f64 = 1e-4;
+
+// two unit vectors that make an orthonormal basis together with the unit vector n
+pub(crate) fn tangents(n: &Vec3) -> (Vec3, Vec3) {
+    let helper = if n.x.abs() > 0.9 {
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 }
+    } else {
+        Vec3 { x: 1.0, y: 0.0, z: 0.0 }
+    };
+    let tangent = cross(&helper, n).normalize();
+    let bitangent = cross(n, &tangent);
+    (tangent, bitangent)
+}
+
+fn unit_axis(axis: usize) -> Vec3 {
+    match axis {
+        0 => Vec3 { x: 1.0, y: 0.0, z: 0.0 },
+        1 => Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        _ => Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+    }
+}
+
+// goes on forever, so there is no bounding box and it can't go into the bvh.
+// the texture repeats every unit along the plane
+pub(crate) struct Plane {
+    pub(crate) point: Vec3,
+    pub(crate) normal: Vec3,
+    pub(crate) material: Arc<dyn Material>,
+}
+
+impl Hitable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let normal = self.normal.normalize();
+        let denominator = dot(&ray.direction, &normal);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+        let time = dot(&(&self.point - &ray.start), &normal) / denominator;
+        if time <= t_min || time >= t_max {
+            return None;
+        }
+
+        let point = ray.eval(time);
+        let (tangent, bitangent) = tangents(&normal);
+        let offset = &point - &self.point;
+        Some(HitRecord {
+            time,
+            u: dot(&offset, &tangent).rem_euclid(1.0),
+            v: dot(&offset, &bitangent).rem_euclid(1.0),
+            point,
+            normal,
+            material: &*self.material,
+            object_id: 0,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+// rectangle lying flat in one of the axis planes, min and max only differ on the other two axes.
+// the normal points along the positive axis unless it's flipped
+pub(crate) struct Rect {
+    pub(crate) axis: usize,
+    pub(crate) min: Vec3,
+    pub(crate) max: Vec3,
+    pub(crate) flip: bool,
+    pub(crate) material: Arc<dyn Material>,
+}
+
+impl Rect {
+    // None unless exactly one axis is flat
+    pub(crate) fn new(min: Vec3, max: Vec3, flip: bool, material: Arc<dyn Material>) -> Option<Rect> {
+        let flat: Vec<usize> = (0..3).filter(|&axis| min.axis(axis) == max.axis(axis)).collect();
+        match flat.as_slice() {
+            [axis] => Some(Rect { axis: *axis, min, max, flip, material }),
+            _ => None,
+        }
+    }
+}
+
+impl Hitable for Rect {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let k = self.min.axis(self.axis);
+        let time = (k - ray.start.axis(self.axis)) / ray.direction.axis(self.axis);
+        if !(time > t_min && time < t_max) {
+            return None;
+        }
+
+        let point = ray.eval(time);
+        let a = (self.axis + 1) % 3;
+        let b = (self.axis + 2) % 3;
+        let u = (point.axis(a) - self.min.axis(a)) / (self.max.axis(a) - self.min.axis(a));
+        let v = (point.axis(b) - self.min.axis(b)) / (self.max.axis(b) - self.min.axis(b));
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+
+        let normal = unit_axis(self.axis);
+        Some(HitRecord {
+            time,
+            point,
+            normal: if self.flip { -&normal } else { normal },
+            u,
+            v,
+            material: &*self.material,
+            object_id: 0,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let pad = PAD * unit_axis(self.axis);
+        Some(Aabb {
+            min: &self.min - &pad,
+            max: &self.max + &pad,
+        })
+    }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        let ray = Ray { start: origin.clone(), direction: direction.clone() };
+        let hitrecord = match self.hit(&ray, 0.001, 99999999.0) {
+            Some(hitrecord) => hitrecord,
+            None => return 0.0,
+        };
+        // a bit of area seen at a slant and from far away covers less solid angle
+        let length = direction.length();
+        let distance_squared = (hitrecord.time * length).powi(2);
+        let cosine = (direction.axis(self.axis) / length).abs();
+        distance_squared / (cosine * self.area())
+    }
+
+    fn random(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let a = (self.axis + 1) % 3;
+        let b = (self.axis + 2) % 3;
+        let (r1, r2) = sampler.next_2d();
+        let point = &self.min
+            + r1 * (self.max.axis(a) - self.min.axis(a)) * unit_axis(a)
+            + r2 * (self.max.axis(b) - self.min.axis(b)) * unit_axis(b);
+        point - origin
+    }
+}
+
+impl Rect {
+    fn area(&self) -> f64 {
+        let a = (self.axis + 1) % 3;
+        let b = (self.axis + 2) % 3;
+        ((self.max.axis(a) - self.min.axis(a)) * (self.max.axis(b) - self.min.axis(b))).abs()
+    }
+}
+
+// axis aligned box made of six rects, named so it doesn't clash with std's Box
+pub(crate) struct Cuboid {
+    min: Vec3,
+    max: Vec3,
+    sides: HitableList,
+}
+
+impl Cuboid {
+    pub(crate) fn new(min: Vec3, max: Vec3, material: Arc<dyn Material>) -> Cuboid {
+        let mut sides: Vec<Box<dyn Hitable>> = Vec::new();
+        for axis in 0..3 {
+            // squash the box onto its low and high side along this axis
+            let mut low_max = max.clone();
+            let mut high_min = min.clone();
+            match axis {
+                0 => { low_max.x = min.x; high_min.x = max.x; }
+                1 => { low_max.y = min.y; high_min.y = max.y; }
+                _ => { low_max.z = min.z; high_min.z = max.z; }
+            }
+            sides.push(Box::new(Rect { axis, min: min.clone(), max: low_max, flip: true, material: material.clone() }));
+            sides.push(Box::new(Rect { axis, min: high_min, max: max.clone(), flip: false, material: material.clone() }));
+        }
+        Cuboid { min, max, sides: HitableList { hitables: sides } }
+    }
+}
+
+impl Hitable for Cuboid {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.sides.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb {
+            min: self.min.clone(),
+            max: self.max.clone(),
+        })
+    }
+}
+
+pub(crate) struct Disk {
+    pub(crate) center: Vec3,
+    pub(crate) normal: Vec3,
+    pub(crate) radius: f64,
+    pub(crate) material: Arc<dyn Material>,
+}
+
+impl Hitable for Disk {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_disk(&self.center, &self.normal.normalize(), self.radius, &*self.material, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(disk_box(&self.center, &self.normal.normalize(), self.radius))
+    }
+
+    // like the rect's, uniform over the area
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        let ray = Ray { start: origin.clone(), direction: direction.clone() };
+        let hitrecord = match self.hit(&ray, 0.001, 99999999.0) {
+            Some(hitrecord) => hitrecord,
+            None => return 0.0,
+        };
+        let length = direction.length();
+        let distance_squared = (hitrecord.time * length).powi(2);
+        let cosine = (dot(direction, &hitrecord.normal) / length).abs();
+        distance_squared / (cosine * PI * self.radius * self.radius)
+    }
+
+    fn random(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (tangent, bitangent) = tangents(&self.normal.normalize());
+        let (r1, r2) = sampler.next_2d();
+        // the square root spreads the points evenly instead of bunching them up in the middle
+        let r = self.radius * r1.sqrt();
+        let phi = 2.0 * PI * r2;
+        &self.center + r * libm::cos(phi) * &tangent + r * libm::sin(phi) * &bitangent - origin
+    }
+}
+
+// u goes around the disk, v from the center to the edge
+fn hit_disk<'a>(center: &Vec3, normal: &Vec3, radius: f64, material: &'a dyn Material,
+                ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+    let denominator = dot(&ray.direction, normal);
+    if denominator.abs() < 1e-12 {
+        return None;
+    }
+    let time = dot(&(center - &ray.start), normal) / denominator;
+    if time <= t_min || time >= t_max {
+        return None;
+    }
+
+    let point = ray.eval(time);
+    let offset = &point - center;
+    let distance = offset.length();
+    if distance > radius {
+        return None;
+    }
+
+    let (tangent, bitangent) = tangents(normal);
+    let angle = libm::atan2(dot(&offset, &bitangent), dot(&offset, &tangent));
+    Some(HitRecord {
+        time,
+        point,
+        normal: normal.clone(),
+        u: (angle + PI) / (2.0 * PI),
+        v: distance / radius,
+        material,
+        object_id: 0,
+    })
+}
+
+// a disk sticks out of its center by radius * sin of the angle between its normal and each axis
+fn disk_box(center: &Vec3, normal: &Vec3, radius: f64) -> Aabb {
+    let extent = |n: f64| radius * (1.0 - n * n).max(0.0).sqrt() + PAD;
+    let extent = Vec3 { x: extent(normal.x), y: extent(normal.y), z: extent(normal.z) };
+    Aabb {
+        min: center - &extent,
+        max: center + &extent,
+    }
+}
+
+// goes from base to top, optionally closed off with a disk on both ends
+pub(crate) struct Cylinder {
+    pub(crate) base: Vec3,
+    pub(crate) top: Vec3,
+    pub(crate) radius: f64,
+    pub(crate) capped: bool,
+    pub(crate) material: Arc<dyn Material>,
+}
+
+impl Hitable for Cylinder {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let axis = &self.top - &self.base;
+        let height = axis.length();
+        let axis = axis.normalize();
+
+        // only the parts perpendicular to the axis matter for hitting the infinite tube
+        let oc = &ray.start - &self.base;
+        let direction_perp = &ray.direction - dot(&ray.direction, &axis) * &axis;
+        let oc_perp = &oc - dot(&oc, &axis) * &axis;
+        let a = dot(&direction_perp, &direction_perp);
+        let b = dot(&oc_perp, &direction_perp);
+        let c = dot(&oc_perp, &oc_perp) - self.radius.powi(2);
+        let discriminant = b*b - a*c;
+
+        let mut result: Option<HitRecord> = None;
+        let mut closest = t_max;
+
+        if a > 1e-12 && discriminant > 0.0 {
+            for &time in &[(-b - discriminant.sqrt()) / a, (-b + discriminant.sqrt()) / a] {
+                if time <= t_min || time >= closest {
+                    continue;
+                }
+                let point = ray.eval(time);
+                let along = dot(&(&point - &self.base), &axis);
+                if along < 0.0 || along > height {
+                    continue;
+                }
+
+                let normal = (&point - &self.base - along * &axis) / self.radius;
+                let (tangent, bitangent) = tangents(&axis);
+                let angle = libm::atan2(dot(&normal, &bitangent), dot(&normal, &tangent));
+                closest = time;
+                result = Some(HitRecord {
+                    time,
+                    point,
+                    normal,
+                    u: (angle + PI) / (2.0 * PI),
+                    v: along / height,
+                    material: &*self.material,
+                    object_id: 0,
+                });
+                break;
+            }
+        }
+
+        if self.capped {
+            let caps = [(&self.base, -&axis), (&self.top, axis.clone())];
+            for (center, normal) in caps.iter() {
+                if let Some(hitrecord) = hit_disk(center, normal, self.radius, &*self.material, ray, t_min, closest) {
+                    closest = hitrecord.time;
+                    result = Some(hitrecord);
+                }
+            }
+        }
+
+        result
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let axis = (&self.top - &self.base).normalize();
+        Some(disk_box(&self.base, &axis, self.radius).union(&disk_box(&self.top, &axis, self.radius)))
+    }
+}
@@ -0,0 +1,117 @@
+// colors that change over the surface, looked up with the hit's uv coordinates or its position
+use std::sync::Arc;
+
+use image::codecs::hdr::HdrDecoder;
+
+use crate::{Color, Vec3};
+
+pub(crate) trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, point: &Vec3) -> Color;
+}
+
+pub(crate) struct SolidColor {
+    pub(crate) color: Color,
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _point: &Vec3) -> Color {
+        self.color.clone()
+    }
+}
+
+// 3d checkerboard, it depends on the position so it doesn't need uvs and doesn't get stretched
+pub(crate) struct Checker {
+    pub(crate) odd: Arc<dyn Texture>,
+    pub(crate) even: Arc<dyn Texture>,
+    // squares per unit times pi
+    pub(crate) scale: f64,
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f64, v: f64, point: &Vec3) -> Color {
+        let sines = libm::sin(self.scale * point.x) * libm::sin(self.scale * point.y) * libm::sin(self.scale * point.z);
+        if sines < 0.0 {
+            self.odd.value(u, v, point)
+        } else {
+            self.even.value(u, v, point)
+        }
+    }
+}
+
+// decoded picture in linear color, the first row is the top of the image
+pub(crate) struct Image {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) pixels: Vec<Color>,
+}
+
+impl Image {
+    // png, jpeg or radiance hdr, whatever the bytes turn out to be
+    pub(crate) fn decode(bytes: &[u8]) -> Result<Image, String> {
+        if let Ok(image::ImageFormat::Hdr) = image::guess_format(bytes) {
+            return Image::decode_hdr(bytes);
+        }
+
+        let decoded = image::load_from_memory(bytes).map_err(|err| err.to_string())?.to_rgb8();
+        let (width, height) = decoded.dimensions();
+        if width == 0 || height == 0 {
+            return Err("the image is empty".to_string());
+        }
+        let pixels = decoded.pixels()
+            .map(|pixel| Color {
+                r: srgb_to_linear(pixel[0]),
+                g: srgb_to_linear(pixel[1]),
+                b: srgb_to_linear(pixel[2]),
+                a: 1.0,
+            })
+            .collect();
+        Ok(Image { width, height, pixels })
+    }
+
+    // the generic image loading squashes hdr down to 8 bits, this keeps the real values.
+    // they're linear already and can go way above 1
+    fn decode_hdr(bytes: &[u8]) -> Result<Image, String> {
+        let decoder = HdrDecoder::new(bytes).map_err(|err| err.to_string())?;
+        let metadata = decoder.metadata();
+        let (width, height) = (metadata.width, metadata.height);
+        if width == 0 || height == 0 {
+            return Err("the image is empty".to_string());
+        }
+        let pixels = decoder.read_image_hdr().map_err(|err| err.to_string())?
+            .iter()
+            .map(|pixel| Color {
+                r: pixel[0] as f64,
+                g: pixel[1] as f64,
+                b: pixel[2] as f64,
+                a: 1.0,
+            })
+            .collect();
+        Ok(Image { width, height, pixels })
+    }
+}
+
+// the files store colors with the srgb curve applied, lighting has to happen on linear values
+fn srgb_to_linear(value: u8) -> f64 {
+    let c = value as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        libm::pow((c + 0.055) / 1.055, 2.4)
+    }
+}
+
+// repeats outside of [0, 1], v = 0 is the bottom of the image
+pub(crate) struct ImageTexture {
+    pub(crate) image: Arc<Image>,
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _point: &Vec3) -> Color {
+        let image = &self.image;
+        let u = u.rem_euclid(1.0);
+        let v = 1.0 - v.rem_euclid(1.0);
+        let col = ((u * image.width as f64) as u32).min(image.width - 1);
+        let row = ((v * image.height as f64) as u32).min(image.height - 1);
+        image.pixels[(row * image.width + col) as usize].clone()
+    }
+}
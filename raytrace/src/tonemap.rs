@@ -0,0 +1,86 @@
+// turns the averaged linear colors, which can go way above 1, into bytes for the screen. the
+// accumulation buffer isn't touched by any of this so it can all change without rendering again
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::Color;
+
+// squeezes everything into [0, 1]
+#[wasm_bindgen]
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMap {
+    // cuts off whatever is above 1, bright lights turn into flat white blobs
+    Clamp,
+    // c / (1 + c), never quite reaches white
+    Reinhard,
+    // the filmic curve from aces, as fitted by krzysztof narkowicz. more contrast than reinhard
+    Aces,
+}
+
+// how the tone mapped values get encoded for the screen
+#[derive(Clone, Copy)]
+pub(crate) enum Transfer {
+    // the piecewise srgb curve, what browsers expect
+    Srgb,
+    Gamma(f64),
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Display {
+    pub(crate) tone_map: ToneMap,
+    // in stops, every 1 doubles the brightness
+    pub(crate) exposure: f64,
+    pub(crate) transfer: Transfer,
+}
+
+impl Display {
+    pub(crate) fn bytes(&self, color: &Color) -> [u8; 4] {
+        let scale = libm::exp2(self.exposure);
+        let channel = |value: f64| to_byte(self.encode(self.tone_map(scale * value)));
+        [channel(color.r), channel(color.g), channel(color.b), 255]
+    }
+
+    fn tone_map(&self, value: f64) -> f64 {
+        let value = value.max(0.0);
+        match self.tone_map {
+            ToneMap::Clamp => value.min(1.0),
+            ToneMap::Reinhard => value / (1.0 + value),
+            ToneMap::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                ((value * (a * value + b)) / (value * (c * value + d) + e)).clamp(0.0, 1.0)
+            }
+        }
+    }
+
+    fn encode(&self, value: f64) -> f64 {
+        match self.transfer {
+            Transfer::Srgb => {
+                if value <= 0.0031308 {
+                    12.92 * value
+                } else {
+                    1.055 * libm::pow(value, 1.0 / 2.4) - 0.055
+                }
+            }
+            Transfer::Gamma(gamma) => libm::pow(value, 1.0 / gamma),
+        }
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display {
+            tone_map: ToneMap::Clamp,
+            exposure: 0.0,
+            transfer: Transfer::Srgb,
+        }
+    }
+}
+
+// anything outside of [0, 1] gets clamped instead of wrapping around, NaN comes out black
+pub(crate) fn to_byte(value: f64) -> u8 {
+    if value.is_nan() {
+        return 0;
+    }
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
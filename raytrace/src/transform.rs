@@ -0,0 +1,283 @@
+// moving, rotating and scaling objects without touching their geometry. the wrapped object is
+// shared, so one mesh can be placed in the scene as often as you like
+use std::sync::Arc;
+
+use crate::bvh::Aabb;
+use crate::sampler::Sampler;
+use crate::{HitRecord, Hitable, Ray, Vec3};
+
+// affine transform, a 3x3 matrix with the translation in the last column
+#[derive(Clone)]
+pub(crate) struct Matrix {
+    m: [[f64; 4]; 3],
+}
+
+impl Matrix {
+    fn identity() -> Matrix {
+        Matrix {
+            m: [[1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0]],
+        }
+    }
+
+    fn translation(offset: &Vec3) -> Matrix {
+        Matrix {
+            m: [[1.0, 0.0, 0.0, offset.x],
+                [0.0, 1.0, 0.0, offset.y],
+                [0.0, 0.0, 1.0, offset.z]],
+        }
+    }
+
+    fn scaling(scale: &Vec3) -> Matrix {
+        Matrix {
+            m: [[scale.x, 0.0, 0.0, 0.0],
+                [0.0, scale.y, 0.0, 0.0],
+                [0.0, 0.0, scale.z, 0.0]],
+        }
+    }
+
+    // counterclockwise around the axis (0 is x, 1 is y, 2 is z) when looking down at it
+    fn rotation(axis: usize, degrees: f64) -> Matrix {
+        let (sin, cos) = libm::sincos(degrees.to_radians());
+        let m = match axis {
+            0 => [[1.0, 0.0, 0.0, 0.0],
+                  [0.0, cos, -sin, 0.0],
+                  [0.0, sin, cos, 0.0]],
+            1 => [[cos, 0.0, sin, 0.0],
+                  [0.0, 1.0, 0.0, 0.0],
+                  [-sin, 0.0, cos, 0.0]],
+            _ => [[cos, -sin, 0.0, 0.0],
+                  [sin, cos, 0.0, 0.0],
+                  [0.0, 0.0, 1.0, 0.0]],
+        };
+        Matrix { m }
+    }
+
+    // first other, then self
+    fn then(&self, other: &Matrix) -> Matrix {
+        let a = &other.m;
+        let b = &self.m;
+        let mut m = [[0.0; 4]; 3];
+        for (row, b_row) in b.iter().enumerate() {
+            for col in 0..4 {
+                m[row][col] = (0..3).map(|k| b_row[k] * a[k][col]).sum::<f64>();
+            }
+            m[row][3] += b_row[3];
+        }
+        Matrix { m }
+    }
+
+    // of the 3x3 part, how much it scales volumes
+    fn determinant(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // None if the matrix squashes things flat, e.g. a scale of 0 on some axis
+    fn inverse(&self) -> Option<Matrix> {
+        let m = &self.m;
+        let det = self.determinant();
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        // adjugate of the 3x3 part divided by the determinant
+        let mut inverse = [[0.0; 4]; 3];
+        for (row, inverse_row) in inverse.iter_mut().enumerate() {
+            for (col, value) in inverse_row.iter_mut().take(3).enumerate() {
+                let (r0, r1) = ((col + 1) % 3, (col + 2) % 3);
+                let (c0, c1) = ((row + 1) % 3, (row + 2) % 3);
+                *value = (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) * inv_det;
+            }
+        }
+        // and the translation gets undone after the rest
+        for row in inverse.iter_mut() {
+            row[3] = -(0..3).map(|k| row[k] * m[k][3]).sum::<f64>();
+        }
+        Some(Matrix { m: inverse })
+    }
+
+    pub(crate) fn point(&self, p: &Vec3) -> Vec3 {
+        let v = self.vector(p);
+        Vec3 { x: v.x + self.m[0][3], y: v.y + self.m[1][3], z: v.z + self.m[2][3] }
+    }
+
+    // directions don't get translated
+    pub(crate) fn vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+
+    // multiplies with the transpose, normals have to go through the inverse transpose
+    fn transposed_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * v.x + m[1][0] * v.y + m[2][0] * v.z,
+            y: m[0][1] * v.x + m[1][1] * v.y + m[2][1] * v.z,
+            z: m[0][2] * v.x + m[1][2] * v.y + m[2][2] * v.z,
+        }
+    }
+}
+
+// translate, rotate_x, rotate_y, rotate_z and scale each move the object a step further, but they
+// don't wrap it again. every step just multiplies into the one matrix, so a chain of them costs a
+// single matrix per ray
+pub(crate) struct Transform {
+    hitable: Arc<dyn Hitable>,
+    to_world: Matrix,
+    to_object: Matrix,
+    bbox: Option<Aabb>,
+}
+
+impl Transform {
+    // the object where it is
+    pub(crate) fn new(hitable: Arc<dyn Hitable>) -> Transform {
+        let bbox = hitable.bounding_box();
+        Transform { hitable, to_world: Matrix::identity(), to_object: Matrix::identity(), bbox }
+    }
+
+    pub(crate) fn translate(self, offset: &Vec3) -> Transform {
+        self.then(Matrix::translation(offset)).expect("a translation can always be undone")
+    }
+
+    // in degrees, counterclockwise when looking down the axis
+    pub(crate) fn rotate_x(self, degrees: f64) -> Transform {
+        self.then(Matrix::rotation(0, degrees)).expect("a rotation can always be undone")
+    }
+
+    pub(crate) fn rotate_y(self, degrees: f64) -> Transform {
+        self.then(Matrix::rotation(1, degrees)).expect("a rotation can always be undone")
+    }
+
+    pub(crate) fn rotate_z(self, degrees: f64) -> Transform {
+        self.then(Matrix::rotation(2, degrees)).expect("a rotation can always be undone")
+    }
+
+    // None if the scale is 0 on some axis
+    pub(crate) fn scale(self, scale: &Vec3) -> Option<Transform> {
+        self.then(Matrix::scaling(scale))
+    }
+
+    // None if the matrix can't be inverted
+    fn then(self, step: Matrix) -> Option<Transform> {
+        let to_world = step.then(&self.to_world);
+        let to_object = to_world.inverse()?;
+
+        // the box around all eight transformed corners of the original box
+        let bbox = self.hitable.bounding_box().map(|bbox| {
+            let corners = (0..8).map(|i| {
+                to_world.point(&Vec3 {
+                    x: if i & 1 == 0 { bbox.min.x } else { bbox.max.x },
+                    y: if i & 2 == 0 { bbox.min.y } else { bbox.max.y },
+                    z: if i & 4 == 0 { bbox.min.z } else { bbox.max.z },
+                })
+            });
+            corners.map(|corner| Aabb { min: corner.clone(), max: corner })
+                .reduce(|a, b| a.union(&b))
+                .unwrap()
+        });
+
+        Some(Transform { hitable: self.hitable, to_world, to_object, bbox })
+    }
+}
+
+impl Hitable for Transform {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // the direction isn't normalized afterwards so t means the same thing in both spaces
+        let object_ray = Ray {
+            start: self.to_object.point(&ray.start),
+            direction: self.to_object.vector(&ray.direction),
+        };
+
+        let mut hitrecord = self.hitable.hit(&object_ray, t_min, t_max)?;
+        hitrecord.point = self.to_world.point(&hitrecord.point);
+        hitrecord.normal = self.to_object.transposed_vector(&hitrecord.normal).normalize();
+        Some(hitrecord)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox.clone()
+    }
+
+    // the object picks its directions in its own space, they only have to be carried over. the
+    // matrix stretches some directions more than others though, and with them the solid angle
+    // around them: a bit of it around the unit direction u grows by |det| / |M u|^3
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        let object_direction = self.to_object.vector(direction);
+        let pdf = self.hitable.pdf_value(&self.to_object.point(origin), &object_direction);
+        if pdf == 0.0 {
+            return 0.0;
+        }
+        let stretch = self.to_world.vector(&object_direction.normalize()).length();
+        pdf * stretch.powi(3) / self.to_world.determinant().abs()
+    }
+
+    fn random(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.to_world.vector(&self.hitable.random(&self.to_object.point(origin), sampler))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::SolidColor;
+    use crate::{Color, Lambertian, Sphere};
+
+    fn vec3(x: f64, y: f64, z: f64) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    fn assert_close(a: &Vec3, b: &Vec3) {
+        let d = a.clone() - b;
+        assert!(d.x.abs() < 1e-9 && d.y.abs() < 1e-9 && d.z.abs() < 1e-9, "{:?} isn't {:?}", (a.x, a.y, a.z), (b.x, b.y, b.z));
+    }
+
+    #[test]
+    fn then_goes_right_to_left() {
+        // scaled first, then moved
+        let matrix = Matrix::translation(&vec3(1.0, 0.0, 0.0)).then(&Matrix::scaling(&vec3(2.0, 2.0, 2.0)));
+        assert_close(&matrix.point(&vec3(1.0, 1.0, 1.0)), &vec3(3.0, 2.0, 2.0));
+        // a quarter turn around z takes x to y
+        let matrix = Matrix::rotation(2, 90.0).then(&Matrix::translation(&vec3(1.0, 0.0, 0.0)));
+        assert_close(&matrix.point(&vec3(0.0, 0.0, 0.0)), &vec3(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let matrix = Matrix::translation(&vec3(1.0, -2.0, 3.0))
+            .then(&Matrix::rotation(1, 30.0))
+            .then(&Matrix::scaling(&vec3(2.0, 0.5, -3.0)))
+            .then(&Matrix::rotation(0, -70.0));
+        let inverse = matrix.inverse().unwrap();
+        for p in [vec3(0.0, 0.0, 0.0), vec3(1.0, 2.0, 3.0), vec3(-4.0, 0.5, 7.0)] {
+            assert_close(&inverse.point(&matrix.point(&p)), &p);
+            assert_close(&matrix.vector(&inverse.vector(&p)), &p);
+        }
+        assert!(Matrix::scaling(&vec3(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    // a unit sphere stretched to twice the width, x^2 / 4 + y^2 + z^2 = 1. its normal at a point is
+    // along (x / 4, y, z), not along the point like the sphere's
+    #[test]
+    fn normals_under_non_uniform_scale() {
+        let material = Arc::new(Lambertian {
+            albedo: Arc::new(SolidColor { color: Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 } }),
+        });
+        let sphere = Arc::new(Sphere { center: vec3(0.0, 0.0, 0.0), radius: 1.0, material });
+        let transform = Transform::new(sphere).scale(&vec3(2.0, 1.0, 1.0)).unwrap().translate(&vec3(0.0, 0.0, -5.0));
+
+        let ray = Ray { start: vec3(1.0, 0.0, 0.0), direction: vec3(0.0, 0.0, -1.0) };
+        let hitrecord = transform.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        let z = (0.75f64).sqrt();
+        assert_close(&hitrecord.point, &vec3(1.0, 0.0, z - 5.0));
+        assert_close(&hitrecord.normal, &vec3(0.25, 0.0, z).normalize());
+    }
+}
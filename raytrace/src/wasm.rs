@@ -0,0 +1,204 @@
+// the browser side: a thin wrapper around Renderer that wasm_bindgen can export. errors turn into
+// js exceptions here and nowhere else
+use wasm_bindgen::prelude::*;
+
+use crate::{Aov, Renderer, SamplerKind, TileOrder, ToneMap};
+
+#[wasm_bindgen]
+pub struct Canvas {
+    renderer: Renderer,
+}
+
+fn js_error(err: String) -> JsValue {
+    JsValue::from_str(&err)
+}
+
+#[wasm_bindgen]
+impl Canvas {
+    pub fn new() -> Canvas {
+        console_error_panic_hook::set_once();
+        Canvas { renderer: Renderer::new() }
+    }
+
+    // points into wasm memory, it moves when the size changes or a scene gets loaded
+    pub fn buf(&self) -> *const u8 {
+        self.renderer.buf().as_ptr()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.renderer.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.renderer.height()
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.renderer.samples()
+    }
+
+    pub fn draw(&mut self) {
+        self.renderer.draw();
+    }
+
+    pub fn render_for(&mut self, ms: f64) -> bool {
+        self.renderer.render_for(ms)
+    }
+
+    pub fn set_tile_order(&mut self, order: TileOrder) {
+        self.renderer.set_tile_order(order);
+    }
+
+    pub fn set_size(&mut self, width: u32, height: u32) -> Result<(), JsValue> {
+        self.renderer.set_size(width, height).map_err(js_error)
+    }
+
+    pub fn set_samples_per_pixel(&mut self, samples_per_pixel: u32) {
+        self.renderer.set_samples_per_pixel(samples_per_pixel);
+    }
+
+    pub fn set_adaptive_sampling(&mut self, min_samples_per_pixel: u32, max_samples_per_pixel: u32,
+                                 threshold: f64) -> Result<(), JsValue> {
+        self.renderer.set_adaptive_sampling(min_samples_per_pixel, max_samples_per_pixel, threshold).map_err(js_error)
+    }
+
+    pub fn set_uniform_sampling(&mut self) {
+        self.renderer.set_uniform_sampling();
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.renderer.set_max_depth(max_depth);
+    }
+
+    pub fn draw_samples(&mut self, n: u32) {
+        self.renderer.draw_samples(n);
+    }
+
+    pub fn set_tone_map(&mut self, tone_map: ToneMap) {
+        self.renderer.set_tone_map(tone_map);
+    }
+
+    pub fn set_exposure(&mut self, exposure: f64) {
+        self.renderer.set_exposure(exposure);
+    }
+
+    pub fn set_srgb_output(&mut self) {
+        self.renderer.set_srgb_output();
+    }
+
+    pub fn set_output_gamma(&mut self, gamma: f64) -> Result<(), JsValue> {
+        self.renderer.set_output_gamma(gamma).map_err(js_error)
+    }
+
+    pub fn set_denoise(&mut self, denoise: bool) {
+        self.renderer.set_denoise(denoise);
+    }
+
+    pub fn set_view(&mut self, view: Aov) {
+        self.renderer.set_view(view);
+    }
+
+    // the aovs, like buf these point into wasm memory. they get filled in when called and stay
+    // the same until the next call, which may move them
+
+    // a f32 per pixel
+    pub fn depth_buf(&mut self) -> *const f32 {
+        self.renderer.depth().as_ptr()
+    }
+
+    // 3 f32 per pixel
+    pub fn normal_buf(&mut self) -> *const f32 {
+        self.renderer.normals().as_ptr()
+    }
+
+    // 3 f32 per pixel
+    pub fn albedo_buf(&mut self) -> *const f32 {
+        self.renderer.albedo().as_ptr()
+    }
+
+    // a u32 per pixel
+    pub fn material_id_buf(&mut self) -> *const u32 {
+        self.renderer.material_ids().as_ptr()
+    }
+
+    // a u32 per pixel
+    pub fn object_id_buf(&mut self) -> *const u32 {
+        self.renderer.object_ids().as_ptr()
+    }
+
+    // a u32 per pixel, how many samples it got. this one points straight at the renderer's counts
+    pub fn sample_count_buf(&self) -> *const u32 {
+        self.renderer.sample_counts().as_ptr()
+    }
+
+    // linear float files of the samples so far, for compositing. the exr has the aovs in it too
+    pub fn pfm(&self) -> Vec<u8> {
+        self.renderer.pfm()
+    }
+
+    pub fn exr(&mut self) -> Vec<u8> {
+        self.renderer.exr()
+    }
+
+    // js numbers don't do u64
+    pub fn set_seed(&mut self, seed: u32) {
+        self.renderer.set_seed(seed as u64);
+    }
+
+    pub fn set_sampler(&mut self, sampler: SamplerKind) {
+        self.renderer.set_sampler(sampler);
+    }
+
+    pub fn reset(&mut self) {
+        self.renderer.reset();
+    }
+
+    // wasm_bindgen can't pass arrays around so the vectors come in as their components
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_camera(&mut self,
+                      from_x: f64, from_y: f64, from_z: f64,
+                      at_x: f64, at_y: f64, at_z: f64,
+                      up_x: f64, up_y: f64, up_z: f64,
+                      vfov: f64) -> Result<(), JsValue> {
+        self.renderer.set_camera([from_x, from_y, from_z], [at_x, at_y, at_z], [up_x, up_y, up_z], vfov)
+            .map_err(js_error)
+    }
+
+    pub fn set_lens(&mut self, aperture: f64, focus_dist: f64) -> Result<(), JsValue> {
+        self.renderer.set_lens(aperture, focus_dist).map_err(js_error)
+    }
+
+    pub fn load_scene(&mut self, json: &str) -> Result<(), JsValue> {
+        self.renderer.load_scene(json).map_err(js_error)
+    }
+
+    pub fn set_background(&mut self, r: f64, g: f64, b: f64) {
+        self.renderer.set_background(r, g, b);
+    }
+
+    pub fn set_sky_background(&mut self) {
+        self.renderer.set_sky_background();
+    }
+
+    pub fn set_environment(&mut self, image: &str, rotation: f64, importance_sampling: bool) -> Result<(), JsValue> {
+        self.renderer.set_environment(image, rotation, importance_sampling).map_err(js_error)
+    }
+
+    pub fn add_image(&mut self, name: &str, bytes: &[u8]) -> Result<(), JsValue> {
+        self.renderer.add_image(name, bytes).map_err(js_error)
+    }
+
+    pub fn add_mesh(&mut self, name: &str, obj: &[u8], mtl: Option<Vec<u8>>) -> Result<(), JsValue> {
+        self.renderer.add_mesh(name, obj, mtl.as_deref()).map_err(js_error)
+    }
+
+    pub fn add_obj(&mut self, obj: &[u8], mtl: Option<Vec<u8>>) -> Result<(), JsValue> {
+        self.renderer.add_obj(obj, mtl.as_deref()).map_err(js_error)
+    }
+}
+
+impl Default for Canvas {
+    fn default() -> Self {
+        Self::new()
+    }
+}